@@ -1,19 +1,54 @@
+use crate::algo::teams::{Party, balance_teams, select_parties};
 use crate::matchmaker::MatchmakerResult::{Matched, Skip};
-use crate::matchmaker::{Matchmaker, MatchmakerResult};
+use crate::matchmaker::{
+    CandidateExplanation, EXPLAIN_CANDIDATES, Explanation, MatchedTeams, Matchmaker,
+    MatchmakerError, MatchmakerResult,
+};
 use serde::{Deserialize, Serialize};
-use serde_json::{Value};
+use serde_json::{Value, json};
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::ops::Sub;
 use tracing::warn;
 use crate::entry::{Entry, EntryId};
 
+/// How the `elo` of a party is derived when metadata holds one value per player.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum EloAggregate {
+    #[default]
+    Average,
+    Max,
+    Min,
+}
+
+impl EloAggregate {
+    fn apply(&self, elos: &[i64]) -> Option<i64> {
+        match self {
+            EloAggregate::Average if !elos.is_empty() => {
+                Some(elos.iter().sum::<i64>() / elos.len() as i64)
+            }
+            EloAggregate::Average => None,
+            EloAggregate::Max => elos.iter().copied().max(),
+            EloAggregate::Min => elos.iter().copied().min(),
+        }
+    }
+}
+
+fn default_number_of_teams() -> i64 {
+    2
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct EloMatchmaker {
     scaling_factor: f64,
     team_size: i64,
     max_skill_diff: i64,
+    #[serde(default = "default_number_of_teams")]
+    number_of_teams: i64,
+    #[serde(default)]
+    aggregate: EloAggregate,
     #[serde(skip)]
     elo_map: BTreeMap<i64, HashSet<EntryId>>,
     #[serde(skip)]
@@ -21,13 +56,41 @@ pub struct EloMatchmaker {
 }
 
 impl EloMatchmaker {
+    pub fn new(
+        scaling_factor: f64,
+        team_size: i64,
+        max_skill_diff: i64,
+        number_of_teams: i64,
+        aggregate: EloAggregate,
+    ) -> Self {
+        Self {
+            scaling_factor,
+            team_size,
+            max_skill_diff,
+            number_of_teams,
+            aggregate,
+            elo_map: BTreeMap::new(),
+            entries: HashMap::new(),
+        }
+    }
 
-    fn get_elo(entry: &Entry) -> Option<i64> {
-        entry.metadata.get("elo").map(|v| v.as_i64()).flatten()
+    /// Reads the `elo` of an entry, either a single value for the whole party or one
+    /// value per player combined with the configured aggregate.
+    fn get_elo(&self, entry: &Entry) -> Option<i64> {
+        match entry.metadata.get("elo")? {
+            Value::Array(values) => {
+                if values.len() != entry.players.len() {
+                    return None;
+                }
+                let elos = values.iter().map(|v| v.as_i64()).collect::<Option<Vec<i64>>>()?;
+                self.aggregate.apply(&elos)
+            }
+            value => value.as_i64(),
+        }
     }
 
     fn get_elo_range(&self, entry: &Entry) -> Result<(i64, i64), &'static str> {
-        let elo = entry.metadata.get("elo").map(|v| v.as_i64()).flatten().ok_or("Entry has no elo")?;
+        let elo = self.get_elo(entry).ok_or("Entry has no elo")?;
 
         // time since queued in seconds
         let duration = chrono::Utc::now().sub(entry.time_queued).as_seconds_f64();
@@ -36,8 +99,16 @@ impl EloMatchmaker {
         Ok((elo - incr, elo + incr))
     }
 
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.team_size < 1 || self.number_of_teams < 1 {
+            return Err("Team size and number of teams must be positive integers".into());
+        }
+        Ok(())
+    }
+
     pub fn deserialize(value: Value) -> Result<Box<dyn Matchmaker + Send + Sync>, Box<dyn Error>> {
         let matchmaker: EloMatchmaker = serde_json::from_value(value)?;
+        matchmaker.validate()?;
         Ok(Box::new(matchmaker))
     }
 }
@@ -47,46 +118,102 @@ impl Matchmaker for EloMatchmaker {
         String::from("elo")
     }
 
+    fn metadata_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "required": ["elo"],
+            "properties": {
+                "elo": {
+                    "oneOf": [
+                        {"type": "integer"},
+                        {"type": "array", "items": {"type": "integer"}, "minItems": 1}
+                    ]
+                }
+            }
+        })
+    }
+
     fn matchmake(&self) -> MatchmakerResult {
-        for (id, entry) in &self.entries {
-            let elo_opt = Self::get_elo(entry);
-            if let None = elo_opt {
-                warn!("Entry {:?} has no elo, which should never happen", id);
+        let team_size = self.team_size as usize;
+        let number_of_teams = self.number_of_teams as usize;
+
+        let mut matches: Vec<MatchedTeams> = Vec::new();
+        let mut taken: HashSet<EntryId> = HashSet::new();
+
+        let mut anchors: Vec<(&EntryId, &Entry)> = self.entries.iter().collect();
+        anchors.sort_by_key(|(_, entry)| entry.time_queued);
+
+        for (id, entry) in anchors {
+            if taken.contains(id) {
                 continue;
             }
-            let elo = elo_opt.unwrap();
+            let Some(elo) = self.get_elo(entry) else {
+                warn!("Entry {:?} has no elo, which should never happen", id);
+                continue;
+            };
             let Ok((lower, upper)) = self.get_elo_range(entry) else {
                 warn!("Entry {:?} has no elo range, which should never happen", id);
                 continue;
             };
 
-            let nearby = self.elo_map.range(lower..=upper);
+            // Closest ratings first, longest waiting first on ties
+            let mut candidates: Vec<(&Entry, i64)> = self
+                .elo_map
+                .range(lower..=upper)
+                .flat_map(|(&nearby_elo, ids)| ids.iter().map(move |x| (x, nearby_elo)))
+                .filter(|(candidate_id, _)| *candidate_id != id && !taken.contains(*candidate_id))
+                .filter(|(_, nearby_elo)| (nearby_elo - elo).abs() <= self.max_skill_diff)
+                .filter_map(|(candidate_id, nearby_elo)| {
+                    self.entries.get(candidate_id).map(|x| (x, nearby_elo))
+                })
+                .collect();
+            candidates.sort_by_key(|(candidate, nearby_elo)| {
+                ((nearby_elo - elo).abs(), candidate.time_queued)
+            });
+            candidates.insert(0, (entry, elo));
 
-            let mut closest_candidate: Option<EntryId> = None;
-            let mut min_diff = i64::MAX;
+            // Each candidate is within maxSkillDiff of the anchor, but the whole group has to be
+            // too, or ratings on both sides of the anchor could be twice as far apart
+            let (mut lowest, mut highest) = (elo, elo);
+            candidates.retain(|(_, nearby_elo)| {
+                let (low, high) = (lowest.min(*nearby_elo), highest.max(*nearby_elo));
+                if high - low > self.max_skill_diff {
+                    return false;
+                }
+                (lowest, highest) = (low, high);
+                true
+            });
 
-            for (&nearby_elo, ids) in nearby {
-                for &candidate_id in ids {
-                    if candidate_id == *id {
-                        continue; // Don't match with self
-                    }
+            let parties: Vec<Party> = candidates
+                .iter()
+                .map(|(candidate, elo)| Party::new(candidate.players.len(), *elo as f64))
+                .collect();
 
-                    let diff = (nearby_elo - elo).abs();
-                    if diff > self.max_skill_diff {
-                        continue; // Skip if difference is too high
-                    }
+            let Some(selected) = select_parties(&parties, team_size, number_of_teams) else {
+                continue;
+            };
+            let selected_parties: Vec<Party> = selected.iter().map(|&i| parties[i]).collect();
+            let Some(teams) = balance_teams(&selected_parties, team_size, number_of_teams) else {
+                continue;
+            };
 
-                    if diff < min_diff {
-                        min_diff = diff;
-                        closest_candidate = Some(candidate_id);
-                    }
-                }
-            }
-            if let Some(opponent) = closest_candidate {
-                return Matched(vec![vec![*id], vec![opponent]]);
-            }
+            let teams: Vec<Vec<EntryId>> = teams
+                .into_iter()
+                .map(|team| {
+                    team.into_iter()
+                        .map(|i| candidates[selected[i]].0.id)
+                        .collect()
+                })
+                .collect();
+
+            taken.extend(teams.iter().flatten().copied());
+            matches.push(teams.into());
+        }
+
+        if matches.is_empty() {
+            return Skip(String::from("No teams found"));
         }
-        Skip(String::from("No teams found"))
+        Matched(matches)
     }
 
     fn serialize(&self) -> Result<Value, Box<dyn Error>> {
@@ -102,12 +229,17 @@ impl Matchmaker for EloMatchmaker {
         self.entries.values().collect()
     }
 
-    fn remove_entry(&mut self, entry_id: &EntryId) -> Result<Entry, Box<dyn Error>> {
-        let entry = self.entries.remove(entry_id).ok_or("Entry not found")?;
-        let elo = EloMatchmaker::get_elo(&entry).ok_or("Entry has no elo")?;
+    fn remove_entry(&mut self, entry_id: &EntryId) -> Result<Entry, MatchmakerError> {
+        let entry = self.entries.remove(entry_id).ok_or(MatchmakerError::EntryNotFound)?;
+        let elo = self
+            .get_elo(&entry)
+            .ok_or_else(|| MatchmakerError::InvalidMetadata(String::from("Entry has no elo")))?;
 
         if let Some(entries) = self.elo_map.get_mut(&elo) {
             entries.remove(entry_id);
+            if entries.is_empty() {
+                self.elo_map.remove(&elo);
+            }
         }
         Ok(entry)
     }
@@ -116,11 +248,13 @@ impl Matchmaker for EloMatchmaker {
         self.entries.get(entry_id)
     }
 
-    fn add_entry(&mut self, entry: Entry) -> Result<(), Box<dyn Error>> {
-        if entry.players.len() != self.team_size as usize {
-            return Err("Entry has wrong team size".into());
+    fn add_entry(&mut self, entry: Entry) -> Result<(), MatchmakerError> {
+        if entry.players.is_empty() || entry.players.len() > self.team_size as usize {
+            return Err(MatchmakerError::WrongTeamSize(String::from("Entry has wrong team size")));
         }
-        let elo = Self::get_elo(&entry).ok_or("Entry has no elo")?;
+        let elo = self
+            .get_elo(&entry)
+            .ok_or_else(|| MatchmakerError::InvalidMetadata(String::from("Entry has no elo")))?;
 
         let id = entry.id;
         self.entries.insert(id, entry);
@@ -128,4 +262,261 @@ impl Matchmaker for EloMatchmaker {
 
         Ok(())
     }
+
+    fn explain(&self, entry_id: &EntryId) -> Result<Explanation, MatchmakerError> {
+        let entry = self.get_entry(entry_id).ok_or(MatchmakerError::EntryNotFound)?;
+        let elo = self
+            .get_elo(entry)
+            .ok_or_else(|| MatchmakerError::InvalidMetadata(String::from("Entry has no elo")))?;
+        let (lower, upper) = self
+            .get_elo_range(entry)
+            .map_err(|err| MatchmakerError::InvalidMetadata(String::from(err)))?;
+
+        let mut nearest: Vec<(&Entry, i64)> = self
+            .entries
+            .values()
+            .filter(|candidate| candidate.id != *entry_id)
+            .filter_map(|candidate| self.get_elo(candidate).map(|x| (candidate, x)))
+            .collect();
+        nearest.sort_by_key(|(candidate, nearby_elo)| ((nearby_elo - elo).abs(), candidate.time_queued));
+
+        let mut players_in_range = entry.players.len();
+        let candidates = nearest
+            .into_iter()
+            .map(|(candidate, nearby_elo)| {
+                let diff = (nearby_elo - elo).abs();
+                if nearby_elo < lower || nearby_elo > upper {
+                    CandidateExplanation::rejected(
+                        candidate.id,
+                        format!("Elo {} is outside the window {}..={}", nearby_elo, lower, upper),
+                    )
+                } else if diff > self.max_skill_diff {
+                    CandidateExplanation::rejected(
+                        candidate.id,
+                        format!("Elo difference {} exceeds maxSkillDiff {}", diff, self.max_skill_diff),
+                    )
+                } else {
+                    players_in_range += candidate.players.len();
+                    CandidateExplanation::accepted(candidate.id, format!("Elo {} is within range", nearby_elo))
+                }
+            })
+            .collect::<Vec<CandidateExplanation>>();
+
+        let needed = (self.team_size * self.number_of_teams) as usize;
+        let summary = if players_in_range >= needed {
+            String::from("Enough players are within range, teams are formed once their party sizes fit")
+        } else {
+            format!("Only {} of {} players needed are within range", players_in_range, needed)
+        };
+
+        Ok(Explanation {
+            entry: *entry_id,
+            matchmaker: self.get_type_name(),
+            summary,
+            details: json!({
+                "elo": elo,
+                "window": {"lower": lower, "upper": upper},
+                "secondsQueued": chrono::Utc::now().sub(entry.time_queued).as_seconds_f64(),
+                "maxSkillDiff": self.max_skill_diff,
+                "playersInRange": players_in_range,
+                "playersNeeded": needed,
+            }),
+            candidates: candidates.into_iter().take(EXPLAIN_CANDIDATES).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::MetadataSchema;
+    use serde_json::{Map, json};
+    use uuid::Uuid;
+
+    fn entry(players: usize, elo: Value) -> Entry {
+        let mut metadata = Map::new();
+        metadata.insert("elo".into(), elo);
+        let mut entry = Entry::new(
+            Uuid::new_v4(),
+            (0..players).map(|_| Uuid::new_v4()).collect(),
+            metadata,
+        );
+        // A minute in queue opens a window of 600 with a scaling factor of 10
+        entry.time_queued -= chrono::Duration::seconds(60);
+        entry
+    }
+
+    #[test]
+    fn test_explain_window() {
+        let mut matchmaker = EloMatchmaker::new(10.0, 1, 100, 2, EloAggregate::Average);
+
+        let waiting = entry(1, json!(1000));
+        let id = waiting.id;
+        matchmaker.add_entry(waiting).unwrap();
+        matchmaker.add_entry(entry(1, json!(1050))).unwrap();
+        matchmaker.add_entry(entry(1, json!(1500))).unwrap();
+        matchmaker.add_entry(entry(1, json!(2000))).unwrap();
+
+        let explanation = matchmaker.explain(&id).unwrap();
+        assert_eq!(explanation.details["elo"], 1000);
+        assert_eq!(explanation.candidates.len(), 3);
+        // Nearest first: in range, over maxSkillDiff, outside the window
+        assert!(explanation.candidates[0].accepted);
+        assert!(explanation.candidates[1].reason.contains("maxSkillDiff"));
+        assert!(explanation.candidates[2].reason.contains("outside the window"));
+        // Nothing was removed
+        assert_eq!(matchmaker.get_entries().len(), 4);
+    }
+
+    #[test]
+    fn test_metadata_schema() {
+        let matchmaker = EloMatchmaker::new(10.0, 2, 100, 2, EloAggregate::Average);
+        let schema = MetadataSchema::new(matchmaker.metadata_schema(), None).unwrap();
+
+        assert!(schema.validate(&entry(1, json!(1000)).metadata).is_ok());
+        assert!(schema.validate(&entry(2, json!([1000, 1200])).metadata).is_ok());
+
+        let error = schema.validate(&entry(1, json!("1000")).metadata).unwrap_err();
+        assert_eq!(error.errors[0].path, "/elo");
+        assert!(schema.validate(&Map::new()).is_err());
+    }
+
+    #[test]
+    fn test_explain_unknown_entry() {
+        let matchmaker = EloMatchmaker::new(10.0, 1, 100, 2, EloAggregate::Average);
+
+        assert!(matchmaker.explain(&EntryId(Uuid::new_v4())).is_err());
+    }
+
+    #[test]
+    fn test_one_versus_one() {
+        let mut matchmaker = EloMatchmaker::new(10.0, 1, 100, 2, EloAggregate::Average);
+
+        matchmaker.add_entry(entry(1, json!(1000))).unwrap();
+        matchmaker.add_entry(entry(1, json!(1050))).unwrap();
+
+        let result = matchmaker.matchmake();
+        let Matched(matches) = result else {
+            panic!("Expected a match, got {:?}", result);
+        };
+        let teams = &matches[0].teams;
+        assert_eq!(teams.len(), 2);
+        assert!(teams.iter().all(|team| team.len() == 1));
+    }
+
+    #[test]
+    fn test_team_sizes_validated() {
+        let settings = |team_size: i64, number_of_teams: i64| {
+            json!({"scalingFactor": 1.0, "teamSize": team_size, "maxSkillDiff": 100, "numberOfTeams": number_of_teams})
+        };
+
+        assert!(EloMatchmaker::deserialize(settings(0, 2)).is_err());
+        assert!(EloMatchmaker::deserialize(settings(2, -1)).is_err());
+        assert!(EloMatchmaker::deserialize(settings(2, 0)).is_err());
+        assert!(EloMatchmaker::deserialize(settings(1, 2)).is_ok());
+    }
+
+    #[test]
+    fn test_oversized_party_rejected() {
+        let mut matchmaker = EloMatchmaker::new(10.0, 2, 100, 2, EloAggregate::Average);
+
+        assert!(matchmaker.add_entry(entry(3, json!(1000))).is_err());
+    }
+
+    #[test]
+    fn test_per_player_elo_must_match_party_size() {
+        let mut matchmaker = EloMatchmaker::new(10.0, 2, 100, 2, EloAggregate::Average);
+
+        assert!(matchmaker.add_entry(entry(2, json!([1000]))).is_err());
+        assert!(matchmaker.add_entry(entry(2, json!([1000, 1100]))).is_ok());
+    }
+
+    #[test]
+    fn test_aggregate() {
+        assert_eq!(EloAggregate::Average.apply(&[1000, 1100]), Some(1050));
+        assert_eq!(EloAggregate::Max.apply(&[1000, 1100]), Some(1100));
+        assert_eq!(EloAggregate::Min.apply(&[1000, 1100]), Some(1000));
+        assert_eq!(EloAggregate::Average.apply(&[]), None);
+    }
+
+    #[test]
+    fn test_balanced_teams_from_parties() {
+        let mut matchmaker = EloMatchmaker::new(10.0, 2, 500, 2, EloAggregate::Average);
+
+        let party = entry(2, json!([1100, 1100]));
+        let party_id = party.id;
+        matchmaker.add_entry(party).unwrap();
+        matchmaker.add_entry(entry(1, json!(1000))).unwrap();
+        matchmaker.add_entry(entry(1, json!(1200))).unwrap();
+
+        let result = matchmaker.matchmake();
+        let Matched(matches) = result else {
+            panic!("Expected a match, got {:?}", result);
+        };
+        let teams = &matches[0].teams;
+
+        // The party plays together, the two solos form the other team
+        assert!(teams.contains(&vec![party_id]));
+        assert!(teams.iter().any(|team| team.len() == 2));
+    }
+
+    #[test]
+    fn test_more_than_two_teams() {
+        let mut matchmaker = EloMatchmaker::new(10.0, 1, 100, 4, EloAggregate::Average);
+
+        for elo in [1000, 1010, 1020] {
+            matchmaker.add_entry(entry(1, json!(elo))).unwrap();
+        }
+        assert!(matchmaker.matchmake().is_skip());
+
+        matchmaker.add_entry(entry(1, json!(1030))).unwrap();
+        let result = matchmaker.matchmake();
+        let Matched(matches) = result else {
+            panic!("Expected a match, got {:?}", result);
+        };
+        let teams = &matches[0].teams;
+        assert_eq!(teams.len(), 4);
+    }
+
+    #[test]
+    fn test_group_spread_within_max_skill_diff() {
+        let mut matchmaker = EloMatchmaker::new(10.0, 1, 100, 3, EloAggregate::Average);
+
+        // Both are within 100 of 1000, but 160 apart from each other
+        for elo in [1000, 920, 1080] {
+            matchmaker.add_entry(entry(1, json!(elo))).unwrap();
+        }
+        assert!(matchmaker.matchmake().is_skip());
+
+        matchmaker.add_entry(entry(1, json!(1010))).unwrap();
+        let result = matchmaker.matchmake();
+        let Matched(matches) = result else {
+            panic!("Expected a match, got {:?}", result);
+        };
+        let elos: Vec<i64> = matches[0]
+            .teams
+            .iter()
+            .flatten()
+            .map(|id| matchmaker.get_elo(&matchmaker.entries[id]).unwrap())
+            .collect();
+        assert!(elos.iter().max().unwrap() - elos.iter().min().unwrap() <= 100);
+    }
+
+    #[test]
+    fn test_multiple_matches_per_tick() {
+        let mut matchmaker = EloMatchmaker::new(10.0, 1, 100, 2, EloAggregate::Average);
+
+        for elo in [1000, 1010, 1500, 1510, 2000] {
+            matchmaker.add_entry(entry(1, json!(elo))).unwrap();
+        }
+
+        let result = matchmaker.matchmake();
+        let Matched(matches) = result else {
+            panic!("Expected a match, got {:?}", result);
+        };
+        assert_eq!(matches.len(), 2);
+
+        let ids: HashSet<EntryId> = matches.iter().flat_map(|x| x.teams.iter().flatten().copied()).collect();
+        assert_eq!(ids.len(), 4);
+    }
 }
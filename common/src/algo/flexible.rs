@@ -1,9 +1,13 @@
+use crate::algo::teams::{Party, balance_teams, select_parties};
 use crate::entry::{Entry, EntryId};
 use crate::matchmaker::MatchmakerResult::Matched;
-use crate::matchmaker::{Matchmaker, MatchmakerResult};
+use crate::matchmaker::{
+    CandidateExplanation, EXPLAIN_CANDIDATES, Explanation, MatchedTeams, Matchmaker,
+    MatchmakerError, MatchmakerResult,
+};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::{HashMap, VecDeque};
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
 
 #[derive(Serialize, Deserialize)]
@@ -13,12 +17,13 @@ pub struct FlexibleMatchMaker {
     team_size: i32,
     max_entry_size: i32,
     min_entry_size: i32,
+    /// Metadata key holding a numeric skill value, used to balance teams when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    skill_key: Option<String>,
     #[serde(skip)]
     addends: Vec<Vec<i32>>,
     #[serde(skip)]
     entries: HashMap<EntryId, Entry>,
-    #[serde(skip)]
-    entries_by_size: HashMap<i32, Vec<EntryId>>,
 }
 
 impl FlexibleMatchMaker {
@@ -29,15 +34,63 @@ impl FlexibleMatchMaker {
         number_of_teams: i32,
     ) -> Result<Self, Box<dyn Error>> {
         let addends = find_unique_addends(team_size)?;
-        Ok(Self {
+        let matchmaker = Self {
             number_of_teams,
             team_size,
             addends,
             max_entry_size,
             min_entry_size,
+            skill_key: None,
             entries: HashMap::new(),
-            entries_by_size: HashMap::new(),
-        })
+        };
+        matchmaker.validate()?;
+        Ok(matchmaker)
+    }
+
+    /// Balances teams on the numeric skill value stored under `skill_key` in entry metadata.
+    pub fn with_skill_key(mut self, skill_key: String) -> Self {
+        self.skill_key = Some(skill_key);
+        self
+    }
+
+    fn get_skill(&self, entry: &Entry) -> Option<f64> {
+        entry.metadata.get(self.skill_key.as_ref()?)?.as_f64()
+    }
+
+    /// Skill of every entry, entries without a skill value get the average of the others
+    /// so they don't pull their team either way.
+    fn get_skills(&self, entries: &[&Entry]) -> Vec<f64> {
+        let skills: Vec<Option<f64>> = entries.iter().map(|entry| self.get_skill(entry)).collect();
+
+        let known: Vec<f64> = skills.iter().flatten().copied().collect();
+        let average = if known.is_empty() {
+            0.0
+        } else {
+            known.iter().sum::<f64>() / known.len() as f64
+        };
+
+        skills.into_iter().map(|x| x.unwrap_or(average)).collect()
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.number_of_teams < 1 {
+            return Err(format!("Number of teams {} must be at least 1", self.number_of_teams).into());
+        }
+        if self.min_entry_size <= 0 || self.min_entry_size > self.max_entry_size {
+            return Err(format!(
+                "Entry size range {}-{} is invalid",
+                self.min_entry_size, self.max_entry_size
+            )
+            .into());
+        }
+        if self.min_entry_size > self.team_size {
+            return Err(format!(
+                "Minimum entry size {} is larger than the team size {}",
+                self.min_entry_size, self.team_size
+            )
+            .into());
+        }
+        Ok(())
     }
 
     fn counter_from_slice(slice: &[i32]) -> HashMap<i32, i32> {
@@ -48,16 +101,6 @@ impl FlexibleMatchMaker {
         counter
     }
 
-    fn use_team(composition: &[i32], available: &HashMap<i32, i32>) -> HashMap<i32, i32> {
-        let mut new = available.clone();
-        for &num in composition {
-            if let Some(count) = new.get_mut(&num) {
-                *count -= 1;
-            }
-        }
-        new
-    }
-
     fn can_form_team(composition: &[i32], available: &HashMap<i32, i32>) -> bool {
         let temp = Self::counter_from_slice(composition);
         for (&num, &count) in &temp {
@@ -68,67 +111,6 @@ impl FlexibleMatchMaker {
         }
         true
     }
-
-    fn backtrack(
-        chosen: &mut Vec<Vec<i32>>,
-        available: &HashMap<i32, i32>,
-        valid_compositions: &[Vec<i32>],
-        num_teams: usize,
-        results: &mut Vec<Vec<Vec<i32>>>,
-    ) {
-        if chosen.len() == num_teams {
-            results.push(chosen.clone());
-            return;
-        }
-
-        for comp in valid_compositions {
-            if Self::can_form_team(comp, available) {
-                let mut new_available = Self::use_team(comp, available);
-                chosen.push(comp.clone());
-                Self::backtrack(
-                    chosen,
-                    &mut new_available,
-                    valid_compositions,
-                    num_teams,
-                    results,
-                );
-                chosen.pop();
-            }
-        }
-    }
-
-    pub fn build_teams(teams: &[i32], team_size: i32, num_teams: usize) -> Option<Vec<Vec<i32>>> {
-        let teams_count = Self::counter_from_slice(teams);
-
-        let Ok(addends) = find_unique_addends(team_size) else {
-            return None;
-        };
-
-        let mut valid_compositions: Vec<Vec<i32>> = Vec::new();
-
-        for addend in &addends {
-            let addend_count = Self::counter_from_slice(addend);
-            let valid = addend_count
-                .iter()
-                .all(|(&k, &v)| teams_count.get(&k).copied().unwrap_or(0) >= v);
-            if valid {
-                valid_compositions.push(addend.clone());
-            }
-        }
-
-        let mut results: Vec<Vec<Vec<i32>>> = Vec::new();
-        let available_counter = Self::counter_from_slice(teams);
-
-        Self::backtrack(
-            &mut vec![],
-            &available_counter,
-            &valid_compositions,
-            num_teams,
-            &mut results,
-        );
-
-        results.into_iter().next()
-    }
 }
 
 impl FlexibleMatchMaker {
@@ -136,6 +118,7 @@ impl FlexibleMatchMaker {
         let mut matchmaker: FlexibleMatchMaker = serde_json::from_value(value)?;
 
         matchmaker.addends = find_unique_addends(matchmaker.team_size)?;
+        matchmaker.validate()?;
 
         Ok(Box::new(matchmaker))
     }
@@ -146,45 +129,73 @@ impl Matchmaker for FlexibleMatchMaker {
         String::from("flexible")
     }
 
+    fn metadata_schema(&self) -> Value {
+        // Entries without a skill value are still accepted, see get_skills
+        match &self.skill_key {
+            Some(key) => json!({"type": "object", "properties": {key: {"type": "number"}}}),
+            None => json!({"type": "object"}),
+        }
+    }
+
     fn matchmake(&self) -> MatchmakerResult {
-        let team_counts = Self::build_teams(
-            self.get_entries()
+        let team_size = self.team_size as usize;
+        let number_of_teams = self.number_of_teams as usize;
+
+        // Longest waiting entries are matched first, and every match is built around the
+        // longest waiting entry that can still be placed
+        let mut waiting: Vec<&Entry> = self.entries.values().collect();
+        waiting.sort_by_key(|entry| entry.time_queued);
+
+        let mut matches: Vec<MatchedTeams> = Vec::new();
+        // If an entry can't be placed, no later entry of the same size can be either
+        let mut unplaceable: HashSet<usize> = HashSet::new();
+        let mut anchor = 0;
+
+        while anchor < waiting.len() {
+            let size = waiting[anchor].players.len();
+            if unplaceable.contains(&size) {
+                anchor += 1;
+                continue;
+            }
+
+            let parties: Vec<Party> = waiting[anchor..]
                 .iter()
-                .map(|e| e.players.len() as i32)
-                .collect::<Vec<i32>>()
-                .as_slice(),
-            self.team_size,
-            self.number_of_teams as usize,
-        );
+                .map(|entry| Party::new(entry.players.len(), 0.0))
+                .collect();
+
+            let Some(selected) = select_parties(&parties, team_size, number_of_teams) else {
+                unplaceable.insert(size);
+                anchor += 1;
+                continue;
+            };
+            let picked: Vec<&Entry> = selected.iter().map(|&i| waiting[anchor + i]).collect();
+            // Among all splits of the picked parties, the one with the smallest skill gap
+            let picked_parties: Vec<Party> = picked
+                .iter()
+                .zip(self.get_skills(&picked))
+                .map(|(entry, skill)| Party::new(entry.players.len(), skill))
+                .collect();
+
+            let Some(teams) = balance_teams(&picked_parties, team_size, number_of_teams) else {
+                unplaceable.insert(size);
+                anchor += 1;
+                continue;
+            };
+
+            let teams: Vec<Vec<EntryId>> = teams
+                .into_iter()
+                .map(|team| team.into_iter().map(|i| picked[i].id).collect())
+                .collect();
+
+            let taken: HashSet<EntryId> = teams.iter().flatten().copied().collect();
+            waiting.retain(|entry| !taken.contains(&entry.id));
+            matches.push(teams.into());
+        }
 
-        let Some(team_counts) = team_counts else {
+        if matches.is_empty() {
             return MatchmakerResult::Skip(String::from("Not enough players to form a match"));
-        };
-
-        let mut index_tracker: HashMap<i32, i32> = HashMap::new();
-        let mut result_teams: Vec<Vec<EntryId>> = Vec::new();
-
-        for sizes in team_counts {
-            let mut team = Vec::new();
-            for size in sizes {
-                let index = *index_tracker.get(&size).unwrap_or(&0);
-                index_tracker.insert(size, index + 1);
-                let by_size = self.entries_by_size.get(&size);
-                let Some(by_size) = by_size else {
-                    return MatchmakerResult::Skip(String::from(""));
-                };
-                let picked = by_size.get(index as usize);
-                let Some(picked) = picked else {
-                    return MatchmakerResult::Skip(String::from(
-                        "Not enough players to form a match",
-                    ));
-                };
-                team.push(picked.clone());
-            }
-            result_teams.push(team);
         }
-
-        Matched(result_teams)
+        Matched(matches)
     }
 
     fn serialize(&self) -> Result<Value, Box<dyn Error>> {
@@ -193,42 +204,116 @@ impl Matchmaker for FlexibleMatchMaker {
     }
 
     fn remove_all(&mut self) -> Vec<Entry> {
-        let entries: Vec<Entry> = self.entries.drain().map(|(_, entry)| entry).collect();
-        self.entries_by_size.clear();
-        entries
+        self.entries.drain().map(|(_, entry)| entry).collect()
     }
 
     fn get_entries(&self) -> Vec<&Entry> {
         self.entries.values().collect()
     }
 
-    fn remove_entry(&mut self, entry_id: &EntryId) -> Result<Entry, Box<dyn Error>> {
-        let entry = self.entries.remove(entry_id);
-        if let Some(entry) = entry {
-            if let Some(teams) = self.entries_by_size.get_mut(&(entry.players.len() as i32)) {
-                teams.retain(|&id| id != *entry_id);
-            }
-            Ok(entry)
-        } else {
-            Err("Entry not found".into())
-        }
+    fn remove_entry(&mut self, entry_id: &EntryId) -> Result<Entry, MatchmakerError> {
+        self.entries
+            .remove(entry_id)
+            .ok_or(MatchmakerError::EntryNotFound)
     }
 
     fn get_entry(&self, entry_id: &EntryId) -> Option<&Entry> {
         self.entries.get(entry_id)
     }
 
-    fn add_entry(&mut self, entry: Entry) -> Result<(), Box<dyn Error>> {
-        let teams = self
-            .entries_by_size
-            .entry(entry.players.len() as i32)
-            .or_insert_with(Vec::new);
-        teams.push(entry.id);
+    fn add_entry(&mut self, entry: Entry) -> Result<(), MatchmakerError> {
+        let size = entry.players.len() as i32;
+        if size < self.min_entry_size || size > self.max_entry_size {
+            return Err(MatchmakerError::WrongTeamSize(format!(
+                "Entry has {} players, but this queue only accepts {} to {} players per entry",
+                size, self.min_entry_size, self.max_entry_size
+            )));
+        }
+        if size > self.team_size {
+            return Err(MatchmakerError::WrongTeamSize(format!(
+                "Entry has {} players, which can never fit in a team of {}",
+                size, self.team_size
+            )));
+        }
 
         self.entries.insert(entry.id, entry);
 
         Ok(())
     }
+
+    fn explain(&self, entry_id: &EntryId) -> Result<Explanation, MatchmakerError> {
+        let entry = self.get_entry(entry_id).ok_or(MatchmakerError::EntryNotFound)?;
+        let size = entry.players.len() as i32;
+
+        let mut waiting: Vec<&Entry> = self
+            .entries
+            .values()
+            .filter(|other| other.id != *entry_id)
+            .collect();
+        waiting.sort_by_key(|other| other.time_queued);
+        waiting.insert(0, entry);
+
+        let sizes: Vec<i32> = waiting.iter().map(|x| x.players.len() as i32).collect();
+        let available = Self::counter_from_slice(&sizes);
+
+        // Every team composition this entry could be part of, and whether the waiting
+        // entries can fill it
+        let compositions: Vec<Value> = self
+            .addends
+            .iter()
+            .filter(|composition| composition.contains(&size))
+            .map(|composition| {
+                json!({
+                    "composition": composition,
+                    "available": Self::can_form_team(composition, &available),
+                })
+            })
+            .collect();
+
+        let parties: Vec<Party> = waiting
+            .iter()
+            .map(|x| Party::new(x.players.len(), 0.0))
+            .collect();
+        let selected = select_parties(&parties, self.team_size as usize, self.number_of_teams as usize);
+
+        let (summary, candidates) = match &selected {
+            Some(selected) => (
+                String::from("A match can be formed around this entry"),
+                selected
+                    .iter()
+                    .skip(1)
+                    .map(|&i| CandidateExplanation::accepted(waiting[i].id, "Fills the remaining slots"))
+                    .take(EXPLAIN_CANDIDATES)
+                    .collect(),
+            ),
+            None => (
+                format!(
+                    "No combination of waiting entries fills {} teams of {} with an entry of {} players",
+                    self.number_of_teams, self.team_size, size
+                ),
+                Vec::new(),
+            ),
+        };
+
+        let mut waiting_sizes: Vec<(i32, i32)> = available.into_iter().collect();
+        waiting_sizes.sort();
+
+        Ok(Explanation {
+            entry: *entry_id,
+            matchmaker: self.get_type_name(),
+            summary,
+            details: json!({
+                "entrySize": size,
+                "waitingSizes": waiting_sizes
+                    .into_iter()
+                    .map(|(size, count)| json!({"size": size, "count": count}))
+                    .collect::<Vec<Value>>(),
+                "compositions": compositions,
+                "placeable": selected.is_some(),
+            }),
+            candidates,
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -331,6 +416,44 @@ mod tests {
         assert!(matchmaker.is_err());
     }
 
+    #[test]
+    fn test_construct_invalid_number_of_teams() {
+        assert!(FlexibleMatchMaker::new(4, 1, 2, 0).is_err());
+        assert!(FlexibleMatchMaker::new(4, 1, 2, -2).is_err());
+
+        let settings = json!({"numberOfTeams": -1, "teamSize": 2, "minEntrySize": 1, "maxEntrySize": 2});
+        assert!(FlexibleMatchMaker::deserialize(settings).is_err());
+    }
+
+    #[test]
+    fn test_construct_invalid_entry_sizes() {
+        assert!(FlexibleMatchMaker::new(4, 3, 2, 2).is_err());
+        assert!(FlexibleMatchMaker::new(4, 0, 2, 2).is_err());
+        assert!(FlexibleMatchMaker::new(2, 3, 3, 2).is_err());
+    }
+
+    #[test]
+    fn test_entry_size_validated() {
+        let mut matchmaker = FlexibleMatchMaker::new(4, 2, 3, 2).unwrap();
+
+        let players = |count: usize| (0..count).map(|_| Uuid::new_v4()).collect::<Vec<Uuid>>();
+
+        assert!(matchmaker.add_entry(Entry::new(Uuid::new_v4(), players(1), Map::new())).is_err());
+        assert!(matchmaker.add_entry(Entry::new(Uuid::new_v4(), players(4), Map::new())).is_err());
+        assert!(matchmaker.add_entry(Entry::new(Uuid::new_v4(), players(2), Map::new())).is_ok());
+        assert!(matchmaker.add_entry(Entry::new(Uuid::new_v4(), players(3), Map::new())).is_ok());
+    }
+
+    #[test]
+    fn test_entry_larger_than_team_rejected() {
+        // Misconfigured queue where the max entry size exceeds the team size
+        let mut matchmaker = FlexibleMatchMaker::new(4, 1, 8, 2).unwrap();
+
+        let players: Vec<Uuid> = (0..8).map(|_| Uuid::new_v4()).collect();
+
+        assert!(matchmaker.add_entry(Entry::new(Uuid::new_v4(), players, Map::new())).is_err());
+    }
+
     #[test]
     fn test_matchmake_success() {
         let mut matchmaker = FlexibleMatchMaker::new(1, 1, 1, 2).unwrap();
@@ -346,6 +469,31 @@ mod tests {
         assert!(result.is_matched());
     }
 
+    #[test]
+    fn test_explain_compositions() {
+        let mut matchmaker = FlexibleMatchMaker::new(3, 1, 3, 2).unwrap();
+        let players = |n: usize| (0..n).map(|_| Uuid::new_v4()).collect::<Vec<Uuid>>();
+
+        let pair = Entry::new(Uuid::new_v4(), players(2), Map::new());
+        let id = pair.id;
+        matchmaker.add_entry(pair).unwrap();
+        matchmaker.add_entry(Entry::new(Uuid::new_v4(), players(3), Map::new())).unwrap();
+
+        let explanation = matchmaker.explain(&id).unwrap();
+        assert_eq!(explanation.details["placeable"], false);
+        // [1, 2] is the only team of 3 containing a pair, and no solo is waiting
+        assert_eq!(
+            explanation.details["compositions"],
+            json!([{"composition": [1, 2], "available": false}])
+        );
+
+        matchmaker.add_entry(Entry::new(Uuid::new_v4(), players(1), Map::new())).unwrap();
+
+        let explanation = matchmaker.explain(&id).unwrap();
+        assert_eq!(explanation.details["placeable"], true);
+        assert_eq!(explanation.candidates.len(), 2);
+    }
+
     #[test]
     fn test_matchmake_not_enough_players() {
         let mut matchmaker = FlexibleMatchMaker::new(5, 1, 5, 2).unwrap();
@@ -377,4 +525,121 @@ mod tests {
 
         println!("{:?}", result);
     }
+
+    #[test]
+    fn test_longest_waiting_entry_prioritised() {
+        let mut matchmaker = FlexibleMatchMaker::new(2, 1, 2, 1).unwrap();
+
+        let mut oldest = Entry::new(Uuid::new_v4(), vec![Uuid::new_v4()], Map::new());
+        oldest.time_queued -= chrono::Duration::seconds(60);
+        let oldest_id = oldest.id;
+
+        matchmaker.add_entry(oldest).unwrap();
+        matchmaker
+            .add_entry(Entry::new(Uuid::new_v4(), vec![Uuid::new_v4(), Uuid::new_v4()], Map::new()))
+            .unwrap();
+        matchmaker
+            .add_entry(Entry::new(Uuid::new_v4(), vec![Uuid::new_v4()], Map::new()))
+            .unwrap();
+
+        let MatchmakerResult::Matched(matches) = matchmaker.matchmake() else {
+            panic!("Expected a match");
+        };
+
+        // The duo fills a team on its own, but the first match is built around the waiting solo
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].teams[0].len(), 2);
+        assert!(matches[0].teams[0].contains(&oldest_id));
+    }
+
+    #[test]
+    fn test_unplaceable_entry_does_not_block_queue() {
+        let mut matchmaker = FlexibleMatchMaker::new(4, 1, 3, 2).unwrap();
+
+        let players = |count: usize| (0..count).map(|_| Uuid::new_v4()).collect::<Vec<Uuid>>();
+
+        // Nothing can fill the trio's team, the duos still get matched
+        let mut trio = Entry::new(Uuid::new_v4(), players(3), Map::new());
+        trio.time_queued -= chrono::Duration::seconds(60);
+        matchmaker.add_entry(trio).unwrap();
+        for _ in 0..4 {
+            matchmaker.add_entry(Entry::new(Uuid::new_v4(), players(2), Map::new())).unwrap();
+        }
+
+        let MatchmakerResult::Matched(matches) = matchmaker.matchmake() else {
+            panic!("Expected a match");
+        };
+        assert_eq!(matches.len(), 1);
+    }
+
+    #[test]
+    fn test_skill_balanced_teams() {
+        let mut matchmaker = FlexibleMatchMaker::new(2, 1, 2, 2)
+            .unwrap()
+            .with_skill_key(String::from("skill"));
+
+        let entry = |skill: i64| {
+            let mut metadata = Map::new();
+            metadata.insert(String::from("skill"), skill.into());
+            Entry::new(Uuid::new_v4(), vec![Uuid::new_v4()], metadata)
+        };
+
+        let strongest = entry(2000);
+        let weakest = entry(1000);
+        let (strongest_id, weakest_id) = (strongest.id, weakest.id);
+
+        matchmaker.add_entry(strongest).unwrap();
+        matchmaker.add_entry(entry(1900)).unwrap();
+        matchmaker.add_entry(entry(1100)).unwrap();
+        matchmaker.add_entry(weakest).unwrap();
+
+        let MatchmakerResult::Matched(matches) = matchmaker.matchmake() else {
+            panic!("Expected a match");
+        };
+
+        // 2000 + 1000 vs 1900 + 1100
+        let teams = &matches[0].teams;
+        assert!(teams.iter().any(|team| team.contains(&strongest_id) && team.contains(&weakest_id)));
+    }
+
+    #[test]
+    fn test_skill_key_from_settings() {
+        let settings = serde_json::json!({
+            "numberOfTeams": 2,
+            "teamSize": 2,
+            "minEntrySize": 1,
+            "maxEntrySize": 2,
+            "skillKey": "mmr"
+        });
+
+        let matchmaker = FlexibleMatchMaker::deserialize(settings).unwrap();
+
+        assert_eq!(matchmaker.serialize().unwrap()["skillKey"], "mmr");
+    }
+
+    #[test]
+    fn test_matchmake_multiple_matches() {
+        let mut matchmaker = FlexibleMatchMaker::new(2, 1, 2, 2).unwrap();
+
+        matchmaker
+            .add_entry(Entry::new(Uuid::new_v4(), vec![Uuid::new_v4(), Uuid::new_v4()], Map::new()))
+            .unwrap();
+        for _ in 0..7 {
+            matchmaker
+                .add_entry(Entry::new(Uuid::new_v4(), vec![Uuid::new_v4()], Map::new()))
+                .unwrap();
+        }
+
+        let MatchmakerResult::Matched(matches) = matchmaker.matchmake() else {
+            panic!("Expected a match");
+        };
+
+        // 9 players, 4 per match
+        assert_eq!(matches.len(), 2);
+        let mut ids: Vec<EntryId> = matches.iter().flat_map(|x| x.teams.concat()).collect();
+        let picked = ids.len();
+        ids.sort_by_key(|id| id.0);
+        ids.dedup();
+        assert_eq!(ids.len(), picked);
+    }
 }
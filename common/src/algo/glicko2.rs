@@ -0,0 +1,265 @@
+use crate::entry::{Entry, EntryId};
+use crate::matchmaker::MatchmakerResult::{Matched, Skip};
+use crate::matchmaker::{MatchedTeams, Matchmaker, MatchmakerError, MatchmakerResult};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::ops::Sub;
+use tracing::warn;
+
+/// Glicko-2 rating of a single entry, read from its metadata.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Glicko2Rating {
+    pub rating: f64,
+    pub rd: f64,
+    pub volatility: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Glicko2Matchmaker {
+    team_size: i64,
+    scaling_factor: f64,
+    rd_factor: f64,
+    max_rating_diff: f64,
+    #[serde(skip)]
+    rating_map: BTreeMap<i64, HashSet<EntryId>>,
+    #[serde(skip)]
+    entries: HashMap<EntryId, Entry>,
+}
+
+impl Glicko2Matchmaker {
+    pub fn new(team_size: i64, scaling_factor: f64, rd_factor: f64, max_rating_diff: f64) -> Self {
+        Self {
+            team_size,
+            scaling_factor,
+            rd_factor,
+            max_rating_diff,
+            rating_map: BTreeMap::new(),
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn get_rating(entry: &Entry) -> Option<Glicko2Rating> {
+        let read = |key: &str| entry.metadata.get(key).and_then(|v| v.as_f64());
+
+        let rating = Glicko2Rating {
+            rating: read("rating")?,
+            rd: read("rd")?,
+            volatility: read("volatility")?,
+        };
+
+        if rating.rd < 0.0 || rating.volatility < 0.0 {
+            return None;
+        }
+        Some(rating)
+    }
+
+    /// Half-width of the search window around an entry's rating. Uncertain
+    /// ratings (high `rd`) start wider, and every entry widens over time.
+    fn get_window(&self, entry: &Entry, rating: &Glicko2Rating) -> f64 {
+        // time since queued in seconds
+        let duration = chrono::Utc::now().sub(entry.time_queued).as_seconds_f64();
+
+        rating.rd * self.rd_factor + duration * self.scaling_factor
+    }
+
+    pub fn deserialize(value: Value) -> Result<Box<dyn Matchmaker + Send + Sync>, Box<dyn Error>> {
+        let matchmaker: Glicko2Matchmaker = serde_json::from_value(value)?;
+        Ok(Box::new(matchmaker))
+    }
+}
+
+impl Matchmaker for Glicko2Matchmaker {
+    fn get_type_name(&self) -> String {
+        String::from("glicko2")
+    }
+
+    fn metadata_schema(&self) -> Value {
+        json!({
+            "type": "object",
+            "required": ["rating", "rd", "volatility"],
+            "properties": {
+                "rating": {"type": "number"},
+                "rd": {"type": "number", "minimum": 0},
+                "volatility": {"type": "number", "minimum": 0}
+            }
+        })
+    }
+
+    fn matchmake(&self) -> MatchmakerResult {
+        let mut matches: Vec<MatchedTeams> = Vec::new();
+        let mut taken: HashSet<EntryId> = HashSet::new();
+
+        let mut anchors: Vec<(&EntryId, &Entry)> = self.entries.iter().collect();
+        anchors.sort_by_key(|(_, entry)| entry.time_queued);
+
+        for (id, entry) in anchors {
+            if taken.contains(id) {
+                continue;
+            }
+            let Some(rating) = Self::get_rating(entry) else {
+                warn!("Entry {:?} has no glicko2 rating, which should never happen", id);
+                continue;
+            };
+            let window = self.get_window(entry, &rating).min(self.max_rating_diff);
+
+            let lower = (rating.rating - window).floor() as i64;
+            let upper = (rating.rating + window).ceil() as i64;
+
+            let mut closest_candidate: Option<EntryId> = None;
+            let mut min_diff = f64::MAX;
+
+            for (_, ids) in self.rating_map.range(lower..=upper) {
+                for candidate_id in ids {
+                    if candidate_id == id || taken.contains(candidate_id) {
+                        continue; // Don't match with self or an entry that is already matched
+                    }
+                    let Some(candidate) = self.entries.get(candidate_id) else {
+                        continue;
+                    };
+                    let Some(candidate_rating) = Self::get_rating(candidate) else {
+                        continue;
+                    };
+
+                    let diff = (candidate_rating.rating - rating.rating).abs();
+                    if diff > window {
+                        continue;
+                    }
+
+                    // Both sides have to accept the match, so an established player is
+                    // not pulled into a wide window opened by an uncertain one.
+                    if diff > self.get_window(candidate, &candidate_rating) {
+                        continue;
+                    }
+
+                    if diff < min_diff {
+                        min_diff = diff;
+                        closest_candidate = Some(*candidate_id);
+                    }
+                }
+            }
+            if let Some(opponent) = closest_candidate {
+                taken.insert(*id);
+                taken.insert(opponent);
+                matches.push(vec![vec![*id], vec![opponent]].into());
+            }
+        }
+
+        if matches.is_empty() {
+            return Skip(String::from("No teams found"));
+        }
+        Matched(matches)
+    }
+
+    fn serialize(&self) -> Result<Value, Box<dyn Error>> {
+        serde_json::to_value(self).map_err(|x| x.into())
+    }
+
+    fn remove_all(&mut self) -> Vec<Entry> {
+        self.rating_map.clear();
+        self.entries.drain().map(|(_, v)| v).collect()
+    }
+
+    fn get_entries(&self) -> Vec<&Entry> {
+        self.entries.values().collect()
+    }
+
+    fn remove_entry(&mut self, entry_id: &EntryId) -> Result<Entry, MatchmakerError> {
+        let entry = self.entries.remove(entry_id).ok_or(MatchmakerError::EntryNotFound)?;
+        let rating = Self::get_rating(&entry).ok_or_else(|| {
+            MatchmakerError::InvalidMetadata(String::from("Entry has no glicko2 rating"))
+        })?;
+
+        let key = rating.rating.round() as i64;
+        if let Some(entries) = self.rating_map.get_mut(&key) {
+            entries.remove(entry_id);
+            if entries.is_empty() {
+                self.rating_map.remove(&key);
+            }
+        }
+        Ok(entry)
+    }
+
+    fn get_entry(&self, entry_id: &EntryId) -> Option<&Entry> {
+        self.entries.get(entry_id)
+    }
+
+    fn add_entry(&mut self, entry: Entry) -> Result<(), MatchmakerError> {
+        if entry.players.len() != self.team_size as usize {
+            return Err(MatchmakerError::WrongTeamSize(String::from("Entry has wrong team size")));
+        }
+        let rating = Self::get_rating(&entry)
+            .ok_or_else(|| {
+                MatchmakerError::InvalidMetadata(String::from(
+                    "Entry needs numeric rating, rd and volatility (rd and volatility >= 0)",
+                ))
+            })?;
+
+        let id = entry.id;
+        self.entries.insert(id, entry);
+        self.rating_map
+            .entry(rating.rating.round() as i64)
+            .or_default()
+            .insert(id);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Map, json};
+    use uuid::Uuid;
+
+    fn entry(rating: f64, rd: f64) -> Entry {
+        let mut metadata = Map::new();
+        metadata.insert("rating".into(), json!(rating));
+        metadata.insert("rd".into(), json!(rd));
+        metadata.insert("volatility".into(), json!(0.06));
+        Entry::new(Uuid::new_v4(), vec![Uuid::new_v4()], metadata)
+    }
+
+    #[test]
+    fn test_missing_rating_rejected() {
+        let mut matchmaker = Glicko2Matchmaker::new(1, 0.0, 1.0, 500.0);
+
+        let mut metadata = Map::new();
+        metadata.insert("rating".into(), json!(1500.0));
+        let entry = Entry::new(Uuid::new_v4(), vec![Uuid::new_v4()], metadata);
+
+        assert!(matchmaker.add_entry(entry).is_err());
+    }
+
+    #[test]
+    fn test_established_players_match_tightly() {
+        let mut matchmaker = Glicko2Matchmaker::new(1, 0.0, 1.0, 500.0);
+
+        matchmaker.add_entry(entry(1500.0, 30.0)).unwrap();
+        matchmaker.add_entry(entry(1600.0, 30.0)).unwrap();
+
+        assert!(matchmaker.matchmake().is_skip());
+    }
+
+    #[test]
+    fn test_uncertain_players_match_loosely() {
+        let mut matchmaker = Glicko2Matchmaker::new(1, 0.0, 1.0, 500.0);
+
+        matchmaker.add_entry(entry(1500.0, 300.0)).unwrap();
+        matchmaker.add_entry(entry(1600.0, 300.0)).unwrap();
+
+        assert!(matchmaker.matchmake().is_matched());
+    }
+
+    #[test]
+    fn test_established_player_not_pulled_into_uncertain_window() {
+        let mut matchmaker = Glicko2Matchmaker::new(1, 0.0, 1.0, 500.0);
+
+        matchmaker.add_entry(entry(1500.0, 300.0)).unwrap();
+        matchmaker.add_entry(entry(1600.0, 30.0)).unwrap();
+
+        assert!(matchmaker.matchmake().is_skip());
+    }
+}
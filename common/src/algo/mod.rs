@@ -1,3 +1,9 @@
 pub mod flexible;
 pub mod elo;
+pub mod glicko2;
+pub mod regional;
+pub mod roles;
+pub mod rules;
+pub mod teams;
+pub mod wasm;
 mod test;
\ No newline at end of file
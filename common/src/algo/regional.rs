@@ -0,0 +1,378 @@
+use crate::entry::{Entry, EntryId};
+use crate::matchmaker;
+use crate::matchmaker::MatchmakerResult::{Matched, Skip};
+use crate::matchmaker::{MatchedTeams, Matchmaker, MatchmakerError, MatchmakerResult};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::ops::Sub;
+use std::sync::Mutex;
+use tracing::warn;
+
+type InnerMatchmaker = Box<dyn Matchmaker + Send + Sync>;
+
+/// Wraps another matchmaker so only entries that share an acceptable region are grouped.
+///
+/// Every tick the inner matchmaker of each region is run over the entries whose ping to that
+/// region is within the current threshold. The threshold grows with time queued, so entries
+/// are added to more regions the longer they wait.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegionalMatchmaker {
+    matchmaker: String,
+    settings: Value,
+    max_ping: f64,
+    ping_scaling_factor: f64,
+    max_ping_limit: f64,
+    #[serde(skip)]
+    entries: HashMap<EntryId, Entry>,
+    /// Checks the metadata of joining entries, never holds any
+    #[serde(skip)]
+    validator: Option<InnerMatchmaker>,
+    /// Inner matchmaker of every region, kept across ticks
+    #[serde(skip)]
+    regions: Mutex<BTreeMap<String, InnerMatchmaker>>,
+}
+
+impl RegionalMatchmaker {
+    pub fn new(
+        matchmaker: String,
+        settings: Value,
+        max_ping: f64,
+        ping_scaling_factor: f64,
+        max_ping_limit: f64,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut matchmaker = Self {
+            matchmaker,
+            settings,
+            max_ping,
+            ping_scaling_factor,
+            max_ping_limit,
+            entries: HashMap::new(),
+            validator: None,
+            regions: Mutex::new(BTreeMap::new()),
+        };
+        matchmaker.validator = Some(matchmaker.create_inner()?);
+        Ok(matchmaker)
+    }
+
+    fn create_inner(&self) -> Result<InnerMatchmaker, Box<dyn Error>> {
+        if self.matchmaker == "regional" {
+            return Err("Regional matchmakers can't be nested".into());
+        }
+        matchmaker::deserialize(self.matchmaker.clone(), self.settings.clone())
+    }
+
+    /// Reads the measured ping (in ms) to every region from an entry's metadata.
+    pub fn get_pings(entry: &Entry) -> Option<BTreeMap<String, f64>> {
+        let pings = entry
+            .metadata
+            .get("pings")?
+            .as_object()?
+            .iter()
+            .map(|(region, ping)| ping.as_f64().map(|ping| (region.clone(), ping)))
+            .collect::<Option<BTreeMap<String, f64>>>()?;
+
+        if pings.is_empty() { None } else { Some(pings) }
+    }
+
+    /// Regions an entry is limited to by the `region` from its players' profiles, one per player
+    /// for parties. `None` if it may be played anywhere.
+    pub fn get_regions(entry: &Entry) -> Option<HashSet<String>> {
+        match entry.metadata.get("region")? {
+            Value::String(region) => Some(HashSet::from([region.clone()])),
+            Value::Array(regions) => Some(regions.iter().filter_map(|x| x.as_str()).map(String::from).collect()),
+            _ => None,
+        }
+    }
+
+    /// Highest ping an entry accepts right now.
+    fn get_ping_threshold(&self, entry: &Entry) -> f64 {
+        // time since queued in seconds
+        let duration = chrono::Utc::now().sub(entry.time_queued).as_seconds_f64();
+
+        (self.max_ping + duration * self.ping_scaling_factor).min(self.max_ping_limit)
+    }
+
+    /// Adds every entry to the inner matchmaker of each region it currently accepts and
+    /// isn't part of yet. Thresholds only grow, so entries never have to leave a region.
+    fn sync_regions(&self, regions: &mut BTreeMap<String, InnerMatchmaker>) -> Result<(), Box<dyn Error>> {
+        for entry in self.entries.values() {
+            let Some(pings) = Self::get_pings(entry) else {
+                warn!("Entry {:?} has no pings, which should never happen", entry.id);
+                continue;
+            };
+            let threshold = self.get_ping_threshold(entry);
+            let allowed = Self::get_regions(entry);
+
+            for (region, ping) in pings {
+                if ping > threshold || allowed.as_ref().is_some_and(|allowed| !allowed.contains(&region)) {
+                    continue;
+                }
+                if !regions.contains_key(&region) {
+                    regions.insert(region.clone(), self.create_inner()?);
+                }
+                let Some(inner) = regions.get_mut(&region) else {
+                    continue;
+                };
+                if inner.get_entry(&entry.id).is_none()
+                    && let Err(err) = inner.add_entry(entry.clone())
+                {
+                    warn!("Entry {:?} rejected in region {}: {}", entry.id, region, err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Worst ping any player of a match has to the region it would be played in.
+    fn worst_ping(&self, matched: &MatchedTeams, region: &str) -> f64 {
+        matched
+            .teams
+            .iter()
+            .flatten()
+            .filter_map(|id| self.entries.get(id))
+            .filter_map(|entry| Self::get_pings(entry)?.get(region).copied())
+            .fold(0.0, f64::max)
+    }
+
+    pub fn deserialize(value: Value) -> Result<Box<dyn Matchmaker + Send + Sync>, Box<dyn Error>> {
+        let mut matchmaker: RegionalMatchmaker = serde_json::from_value(value)?;
+        matchmaker.validator = Some(matchmaker.create_inner()?);
+        Ok(Box::new(matchmaker))
+    }
+}
+
+impl Matchmaker for RegionalMatchmaker {
+    fn get_type_name(&self) -> String {
+        String::from("regional")
+    }
+
+    fn metadata_schema(&self) -> Value {
+        let pings = json!({
+            "type": "object",
+            "required": ["pings"],
+            "properties": {
+                "pings": {
+                    "type": "object",
+                    "additionalProperties": {"type": "number"},
+                    "minProperties": 1
+                },
+                "region": {
+                    "oneOf": [
+                        {"type": "string"},
+                        {"type": "array", "items": {"type": "string"}, "minItems": 1}
+                    ]
+                }
+            }
+        });
+
+        match &self.validator {
+            Some(inner) => json!({"allOf": [pings, inner.metadata_schema()]}),
+            None => pings,
+        }
+    }
+
+    fn matchmake(&self) -> MatchmakerResult {
+        let mut candidates: Vec<(f64, MatchedTeams)> = Vec::new();
+
+        let mut regions = self.regions.lock().unwrap_or_else(|err| err.into_inner());
+        if let Err(err) = self.sync_regions(&mut regions) {
+            return MatchmakerResult::Error(err.to_string(), None);
+        }
+
+        for (region, inner) in regions.iter() {
+            match inner.matchmake() {
+                Matched(matches) => {
+                    for mut matched in matches {
+                        let ping = self.worst_ping(&matched, region);
+                        matched.region = Some(region.clone());
+                        candidates.push((ping, matched));
+                    }
+                }
+                MatchmakerResult::Error(err, entry) => {
+                    return MatchmakerResult::Error(err, entry);
+                }
+                Skip(_) => {}
+            }
+        }
+
+        // Entries can be matched in several regions, keep the lowest ping match for each
+        candidates.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+        let mut taken: HashSet<EntryId> = HashSet::new();
+        let mut matches: Vec<MatchedTeams> = Vec::new();
+
+        for (_, matched) in candidates {
+            if matched.teams.iter().flatten().any(|id| taken.contains(id)) {
+                continue;
+            }
+            taken.extend(matched.teams.iter().flatten().copied());
+            matches.push(matched);
+        }
+
+        if matches.is_empty() {
+            return Skip(String::from("No match found in any region"));
+        }
+        Matched(matches)
+    }
+
+    fn serialize(&self) -> Result<Value, Box<dyn Error>> {
+        serde_json::to_value(self).map_err(|x| x.into())
+    }
+
+    fn remove_all(&mut self) -> Vec<Entry> {
+        self.regions.get_mut().unwrap_or_else(|err| err.into_inner()).clear();
+        self.entries.drain().map(|(_, v)| v).collect()
+    }
+
+    fn get_entries(&self) -> Vec<&Entry> {
+        self.entries.values().collect()
+    }
+
+    fn remove_entry(&mut self, entry_id: &EntryId) -> Result<Entry, MatchmakerError> {
+        if !self.entries.contains_key(entry_id) {
+            return Err(MatchmakerError::EntryNotFound);
+        }
+        // Only forget the entry once no region holds it, so a failure leaves both in sync
+        for inner in self.regions.get_mut().unwrap_or_else(|err| err.into_inner()).values_mut() {
+            if inner.get_entry(entry_id).is_some() {
+                inner.remove_entry(entry_id)?;
+            }
+        }
+        self.entries.remove(entry_id).ok_or(MatchmakerError::EntryNotFound)
+    }
+
+    fn get_entry(&self, entry_id: &EntryId) -> Option<&Entry> {
+        self.entries.get(entry_id)
+    }
+
+    fn add_entry(&mut self, entry: Entry) -> Result<(), MatchmakerError> {
+        Self::get_pings(&entry).ok_or_else(|| {
+            MatchmakerError::InvalidMetadata(String::from(
+                "Entry needs a map of region to ping (ms) in 'pings'",
+            ))
+        })?;
+
+        // Let the inner matchmaker validate the rest of the metadata
+        let validator = self
+            .validator
+            .as_mut()
+            .ok_or_else(|| MatchmakerError::Internal(String::from("Inner matchmaker is missing")))?;
+        validator.add_entry(entry.clone())?;
+        validator.remove_entry(&entry.id)?;
+
+        self.entries.insert(entry.id, entry);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Map, json};
+    use uuid::Uuid;
+
+    fn matchmaker() -> RegionalMatchmaker {
+        let settings = json!({"numberOfTeams": 2, "teamSize": 1, "maxEntrySize": 1, "minEntrySize": 1});
+        RegionalMatchmaker::new(String::from("flexible"), settings, 50.0, 0.0, 150.0).unwrap()
+    }
+
+    fn entry(pings: Value) -> Entry {
+        let mut metadata = Map::new();
+        metadata.insert("pings".into(), pings);
+        Entry::new(Uuid::new_v4(), vec![Uuid::new_v4()], metadata)
+    }
+
+    #[test]
+    fn test_missing_pings_rejected() {
+        let mut matchmaker = matchmaker();
+
+        assert!(matchmaker.add_entry(entry(json!({}))).is_err());
+        assert!(matchmaker.add_entry(entry(json!({"eu": "fast"}))).is_err());
+    }
+
+    #[test]
+    fn test_no_shared_region() {
+        let mut matchmaker = matchmaker();
+
+        matchmaker.add_entry(entry(json!({"eu": 20, "na": 120}))).unwrap();
+        matchmaker.add_entry(entry(json!({"eu": 130, "na": 30}))).unwrap();
+
+        assert!(matchmaker.matchmake().is_skip());
+    }
+
+    #[test]
+    fn test_shared_region_chosen() {
+        let mut matchmaker = matchmaker();
+
+        matchmaker.add_entry(entry(json!({"eu": 20, "na": 40}))).unwrap();
+        matchmaker.add_entry(entry(json!({"eu": 45, "na": 30}))).unwrap();
+
+        let result = matchmaker.matchmake();
+        let Matched(matches) = result else {
+            panic!("Expected a match, got {:?}", result);
+        };
+        let matched = &matches[0];
+        // Worst ping is 45 in eu and 40 in na
+        assert_eq!(matched.region, Some(String::from("na")));
+    }
+
+    #[test]
+    fn test_profile_region_limits_regions() {
+        let mut matchmaker = matchmaker();
+
+        matchmaker.add_entry(entry(json!({"eu": 20, "na": 40}))).unwrap();
+        let mut limited = entry(json!({"eu": 45, "na": 30}));
+        limited.metadata.insert("region".into(), json!("eu"));
+        matchmaker.add_entry(limited).unwrap();
+
+        let result = matchmaker.matchmake();
+        let Matched(matches) = result else {
+            panic!("Expected a match, got {:?}", result);
+        };
+        // na has the lower worst ping, but the second entry may only play in eu
+        assert_eq!(matches[0].region, Some(String::from("eu")));
+    }
+
+    #[test]
+    fn test_removed_entry_leaves_regions() {
+        let mut matchmaker = matchmaker();
+
+        let first = entry(json!({"eu": 20}));
+        let first_id = first.id;
+        matchmaker.add_entry(first).unwrap();
+        // Adds the first entry to the eu matchmaker, which is kept for the next tick
+        assert!(matchmaker.matchmake().is_skip());
+
+        matchmaker.remove_entry(&first_id).unwrap();
+        matchmaker.add_entry(entry(json!({"eu": 25}))).unwrap();
+        assert!(matchmaker.matchmake().is_skip());
+
+        matchmaker.add_entry(entry(json!({"eu": 30}))).unwrap();
+        let result = matchmaker.matchmake();
+        let Matched(matches) = result else {
+            panic!("Expected a match, got {:?}", result);
+        };
+        assert!(!matches[0].teams.iter().flatten().any(|id| *id == first_id));
+    }
+
+    #[test]
+    fn test_threshold_relaxes_over_time() {
+        let mut matchmaker = matchmaker();
+        matchmaker.ping_scaling_factor = 1.0;
+
+        let mut waiting = entry(json!({"eu": 20, "na": 110}));
+        waiting.time_queued -= chrono::Duration::seconds(60);
+        matchmaker.add_entry(waiting).unwrap();
+        matchmaker.add_entry(entry(json!({"na": 30}))).unwrap();
+
+        let result = matchmaker.matchmake();
+        let Matched(matches) = result else {
+            panic!("Expected a match, got {:?}", result);
+        };
+        let matched = &matches[0];
+        assert_eq!(matched.region, Some(String::from("na")));
+    }
+}
@@ -0,0 +1,352 @@
+use crate::entry::{Entry, EntryId};
+use crate::matchmaker::MatchmakerResult::{Matched, Skip};
+use crate::matchmaker::{MatchedTeams, Matchmaker, MatchmakerError, MatchmakerResult};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use uuid::Uuid;
+
+/// Upper bound on search steps per tick, so a large queue can't stall the tracker.
+const SEARCH_BUDGET: usize = 50_000;
+
+fn default_number_of_teams() -> usize {
+    2
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RoleMatchmaker {
+    /// Number of slots per role on every team, e.g. `{"tank": 1, "damage": 2, "support": 2}`
+    roles: BTreeMap<String, usize>,
+    #[serde(default = "default_number_of_teams")]
+    number_of_teams: usize,
+    #[serde(skip)]
+    entries: HashMap<EntryId, Entry>,
+}
+
+impl RoleMatchmaker {
+    pub fn new(roles: BTreeMap<String, usize>, number_of_teams: usize) -> Result<Self, Box<dyn Error>> {
+        let matchmaker = Self {
+            roles,
+            number_of_teams,
+            entries: HashMap::new(),
+        };
+        matchmaker.validate()?;
+        Ok(matchmaker)
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.team_size() == 0 {
+            return Err("Role template must have at least one slot".into());
+        }
+        if self.number_of_teams == 0 {
+            return Err("Number of teams must be a positive integer".into());
+        }
+        Ok(())
+    }
+
+    fn team_size(&self) -> usize {
+        self.roles.values().sum()
+    }
+
+    /// Reads the acceptable roles of every player in an entry, in order of preference.
+    ///
+    /// Parties use one list per player (`[["tank"], ["damage", "support"]]`), single
+    /// players can use a flat list (`["tank", "damage"]`).
+    pub fn get_player_roles(entry: &Entry) -> Option<Vec<Vec<String>>> {
+        let values = entry.metadata.get("roles")?.as_array()?;
+
+        let read = |values: &Vec<Value>| {
+            values
+                .iter()
+                .map(|v| v.as_str().map(String::from))
+                .collect::<Option<Vec<String>>>()
+        };
+
+        let roles = if entry.players.len() == 1 && values.iter().all(|v| v.is_string()) {
+            vec![read(values)?]
+        } else {
+            values
+                .iter()
+                .map(|v| v.as_array().and_then(read))
+                .collect::<Option<Vec<Vec<String>>>>()?
+        };
+
+        if roles.len() != entry.players.len() {
+            return None;
+        }
+        Some(roles)
+    }
+
+    pub fn deserialize(value: Value) -> Result<Box<dyn Matchmaker + Send + Sync>, Box<dyn Error>> {
+        let matchmaker: RoleMatchmaker = serde_json::from_value(value)?;
+        matchmaker.validate()?;
+        Ok(Box::new(matchmaker))
+    }
+}
+
+impl Matchmaker for RoleMatchmaker {
+    fn get_type_name(&self) -> String {
+        String::from("roles")
+    }
+
+    fn metadata_schema(&self) -> Value {
+        let roles = json!({"type": "array", "items": {"type": "string"}, "minItems": 1});
+        json!({
+            "type": "object",
+            "required": ["roles"],
+            "properties": {
+                "roles": {
+                    "anyOf": [roles, {"type": "array", "items": roles, "minItems": 1}]
+                }
+            }
+        })
+    }
+
+    fn matchmake(&self) -> MatchmakerResult {
+        let mut candidates: Vec<(&Entry, Vec<Vec<String>>)> = self
+            .entries
+            .values()
+            .filter_map(|entry| Self::get_player_roles(entry).map(|roles| (entry, roles)))
+            .collect();
+        // Longest waiting entries get the first pick of slots
+        candidates.sort_by_key(|(entry, _)| entry.time_queued);
+
+        let mut matches: Vec<MatchedTeams> = Vec::new();
+
+        loop {
+            let total_players: usize = candidates.iter().map(|(entry, _)| entry.players.len()).sum();
+            if total_players < self.team_size() * self.number_of_teams {
+                break;
+            }
+
+            let mut search = RoleSearch {
+                candidates: &candidates,
+                open: vec![self.roles.clone(); self.number_of_teams],
+                picked: Vec::new(),
+                roles: HashMap::new(),
+                budget: SEARCH_BUDGET,
+            };
+
+            if !search.fill(0) {
+                break;
+            }
+
+            let mut teams: Vec<Vec<EntryId>> = vec![Vec::new(); self.number_of_teams];
+            for &(index, team) in &search.picked {
+                teams[team].push(candidates[index].0.id);
+            }
+            let roles = search.roles;
+
+            let taken: HashSet<EntryId> = teams.iter().flatten().copied().collect();
+            candidates.retain(|(entry, _)| !taken.contains(&entry.id));
+            matches.push(MatchedTeams::new(teams, roles));
+        }
+
+        if matches.is_empty() {
+            return Skip(String::from("Not enough players to fill every role"));
+        }
+        Matched(matches)
+    }
+
+    fn serialize(&self) -> Result<Value, Box<dyn Error>> {
+        serde_json::to_value(self).map_err(|x| x.into())
+    }
+
+    fn remove_all(&mut self) -> Vec<Entry> {
+        self.entries.drain().map(|(_, v)| v).collect()
+    }
+
+    fn get_entries(&self) -> Vec<&Entry> {
+        self.entries.values().collect()
+    }
+
+    fn remove_entry(&mut self, entry_id: &EntryId) -> Result<Entry, MatchmakerError> {
+        self.entries
+            .remove(entry_id)
+            .ok_or(MatchmakerError::EntryNotFound)
+    }
+
+    fn get_entry(&self, entry_id: &EntryId) -> Option<&Entry> {
+        self.entries.get(entry_id)
+    }
+
+    fn add_entry(&mut self, entry: Entry) -> Result<(), MatchmakerError> {
+        if entry.players.is_empty() || entry.players.len() > self.team_size() {
+            return Err(MatchmakerError::WrongTeamSize(String::from("Entry has wrong team size")));
+        }
+        let player_roles = Self::get_player_roles(&entry)
+            .ok_or_else(|| {
+                MatchmakerError::InvalidMetadata(String::from(
+                    "Entry needs a list of acceptable roles for every player",
+                ))
+            })?;
+
+        for roles in &player_roles {
+            if !roles.iter().any(|role| self.roles.contains_key(role)) {
+                return Err(MatchmakerError::InvalidMetadata(format!(
+                    "Every player needs at least one of the roles: {}",
+                    self.roles.keys().cloned().collect::<Vec<String>>().join(", ")
+                )));
+            }
+        }
+
+        self.entries.insert(entry.id, entry);
+        Ok(())
+    }
+}
+
+/// Depth-first search that places entries (oldest first) on teams, giving every player
+/// one of their acceptable roles, until all slots of all teams are filled.
+struct RoleSearch<'a> {
+    candidates: &'a [(&'a Entry, Vec<Vec<String>>)],
+    open: Vec<BTreeMap<String, usize>>,
+    picked: Vec<(usize, usize)>,
+    roles: HashMap<Uuid, String>,
+    budget: usize,
+}
+
+impl RoleSearch<'_> {
+    fn is_full(&self) -> bool {
+        self.open.iter().all(|team| team.values().all(|&count| count == 0))
+    }
+
+    fn fill(&mut self, index: usize) -> bool {
+        if self.is_full() {
+            return true;
+        }
+        if index >= self.candidates.len() || self.budget == 0 {
+            return false;
+        }
+        self.budget -= 1;
+
+        let size = self.candidates[index].0.players.len();
+        for team in 0..self.open.len() {
+            let free: usize = self.open[team].values().sum();
+            // Teams with the same open slots are interchangeable
+            if free < size || self.open[..team].contains(&self.open[team]) {
+                continue;
+            }
+
+            self.picked.push((index, team));
+            if self.assign(index, 0, team) {
+                return true;
+            }
+            self.picked.pop();
+        }
+
+        self.fill(index + 1)
+    }
+
+    fn assign(&mut self, index: usize, player: usize, team: usize) -> bool {
+        let (entry, player_roles) = &self.candidates[index];
+        if player == entry.players.len() {
+            return self.fill(index + 1);
+        }
+        let player_id = entry.players[player];
+
+        for role in &player_roles[player] {
+            let Some(count) = self.open[team].get_mut(role) else {
+                continue;
+            };
+            if *count == 0 {
+                continue;
+            }
+            *count -= 1;
+            self.roles.insert(player_id, role.clone());
+
+            if self.assign(index, player + 1, team) {
+                return true;
+            }
+
+            self.roles.remove(&player_id);
+            if let Some(count) = self.open[team].get_mut(role) {
+                *count += 1;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Map, json};
+
+    fn matchmaker() -> RoleMatchmaker {
+        let roles = BTreeMap::from([
+            (String::from("tank"), 1),
+            (String::from("damage"), 1),
+        ]);
+        RoleMatchmaker::new(roles, 2).unwrap()
+    }
+
+    fn entry(roles: Value, players: usize) -> Entry {
+        let mut metadata = Map::new();
+        metadata.insert("roles".into(), roles);
+        Entry::new(
+            Uuid::new_v4(),
+            (0..players).map(|_| Uuid::new_v4()).collect(),
+            metadata,
+        )
+    }
+
+    #[test]
+    fn test_unknown_role_rejected() {
+        let mut matchmaker = matchmaker();
+
+        assert!(matchmaker.add_entry(entry(json!(["healer"]), 1)).is_err());
+        assert!(matchmaker.add_entry(entry(json!(["healer", "tank"]), 1)).is_ok());
+    }
+
+    #[test]
+    fn test_party_needs_roles_per_player() {
+        let mut matchmaker = matchmaker();
+
+        assert!(matchmaker.add_entry(entry(json!([["tank"]]), 2)).is_err());
+        assert!(matchmaker.add_entry(entry(json!([["tank"], ["damage"]]), 2)).is_ok());
+    }
+
+    #[test]
+    fn test_skip_until_every_slot_filled() {
+        let mut matchmaker = matchmaker();
+
+        for _ in 0..4 {
+            matchmaker.add_entry(entry(json!(["damage"]), 1)).unwrap();
+        }
+        assert!(matchmaker.matchmake().is_skip());
+    }
+
+    #[test]
+    fn test_roles_assigned() {
+        let mut matchmaker = matchmaker();
+
+        let flex = entry(json!(["damage", "tank"]), 1);
+        let flex_player = flex.players[0];
+        matchmaker.add_entry(flex).unwrap();
+        matchmaker.add_entry(entry(json!(["damage"]), 1)).unwrap();
+        matchmaker.add_entry(entry(json!(["damage"]), 1)).unwrap();
+        matchmaker.add_entry(entry(json!(["tank"]), 1)).unwrap();
+
+        let result = matchmaker.matchmake();
+        let Matched(matches) = result else {
+            panic!("Expected a match, got {:?}", result);
+        };
+        let matched = &matches[0];
+
+        assert_eq!(matched.teams.len(), 2);
+        assert_eq!(matched.roles.len(), 4);
+        // Both damage-only players took the damage slots, so the flexible player tanks
+        assert_eq!(matched.roles[&flex_player], "tank");
+        for team in &matched.teams {
+            let roles: Vec<&String> = team
+                .iter()
+                .flat_map(|id| &matchmaker.get_entry(id).unwrap().players)
+                .map(|player| &matched.roles[player])
+                .collect();
+            assert!(roles.contains(&&String::from("tank")));
+            assert!(roles.contains(&&String::from("damage")));
+        }
+    }
+}
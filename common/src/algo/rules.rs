@@ -0,0 +1,449 @@
+use crate::algo::teams::{Party, balance_teams, select_parties};
+use crate::entry::{Entry, EntryId};
+use crate::matchmaker::MatchmakerResult::{Matched, Skip};
+use crate::matchmaker::{MatchedTeams, Matchmaker, MatchmakerError, MatchmakerResult};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::ops::Sub;
+
+/// A single declarative rule evaluated over `Entry::metadata`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum Rule {
+    /// Every entry in a match has the same value for `key`.
+    Equal { key: String },
+    /// Numeric values of `key` are at most `max_distance` apart. The distance grows by
+    /// `scaling_factor` per second queued, up to `max_limit`.
+    Distance {
+        key: String,
+        max_distance: f64,
+        #[serde(default)]
+        scaling_factor: f64,
+        #[serde(default)]
+        max_limit: Option<f64>,
+    },
+    /// The lists of strings under `key` of all entries in a match share at least one value.
+    Intersect { key: String },
+    /// Parties must have between `min` and `max` players.
+    PartySize { min: usize, max: usize },
+}
+
+impl Rule {
+    /// Checks that an entry carries the metadata the rule needs.
+    fn validate(&self, entry: &Entry) -> Result<(), MatchmakerError> {
+        let missing = |message: String| MatchmakerError::InvalidMetadata(message);
+
+        match self {
+            Rule::Equal { key } => entry
+                .metadata
+                .get(key)
+                .map(|_| ())
+                .ok_or_else(|| missing(format!("Entry has no '{}'", key))),
+            Rule::Distance { key, .. } => Self::get_number(entry, key)
+                .map(|_| ())
+                .ok_or_else(|| missing(format!("Entry has no numeric '{}'", key))),
+            Rule::Intersect { key } => Self::get_set(entry, key)
+                .map(|_| ())
+                .ok_or_else(|| missing(format!("Entry has no list of strings '{}'", key))),
+            Rule::PartySize { min, max } => {
+                let size = entry.players.len();
+                if size < *min || size > *max {
+                    Err(MatchmakerError::WrongTeamSize(format!(
+                        "Party size must be between {} and {}",
+                        min, max
+                    )))
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Metadata key and JSON Schema of the value the rule reads, if any.
+    fn metadata_schema(&self) -> Option<(&String, Value)> {
+        match self {
+            Rule::Equal { key } => Some((key, json!({}))),
+            Rule::Distance { key, .. } => Some((key, json!({"type": "number"}))),
+            Rule::Intersect { key } => {
+                Some((key, json!({"type": "array", "items": {"type": "string"}})))
+            }
+            Rule::PartySize { .. } => None,
+        }
+    }
+
+    /// Whether two entries may be part of the same match.
+    fn compatible(&self, a: &Entry, b: &Entry) -> bool {
+        match self {
+            Rule::Equal { key } => a.metadata.get(key) == b.metadata.get(key),
+            Rule::Distance { key, .. } => {
+                let (Some(x), Some(y)) = (Self::get_number(a, key), Self::get_number(b, key)) else {
+                    return false;
+                };
+                // Both entries have to accept the distance
+                (x - y).abs() <= self.get_distance(a).min(self.get_distance(b))
+            }
+            Rule::Intersect { key } => match (Self::get_set(a, key), Self::get_set(b, key)) {
+                (Some(x), Some(y)) => !x.is_disjoint(&y),
+                _ => false,
+            },
+            Rule::PartySize { .. } => true,
+        }
+    }
+
+    fn get_distance(&self, entry: &Entry) -> f64 {
+        let Rule::Distance {
+            max_distance,
+            scaling_factor,
+            max_limit,
+            ..
+        } = self
+        else {
+            return 0.0;
+        };
+
+        // time since queued in seconds
+        let duration = chrono::Utc::now().sub(entry.time_queued).as_seconds_f64();
+        let distance = max_distance + duration * scaling_factor;
+
+        match max_limit {
+            Some(limit) => distance.min(*limit),
+            None => distance,
+        }
+    }
+
+    fn get_number(entry: &Entry, key: &str) -> Option<f64> {
+        entry.metadata.get(key)?.as_f64()
+    }
+
+    fn get_set(entry: &Entry, key: &str) -> Option<HashSet<String>> {
+        entry
+            .metadata
+            .get(key)?
+            .as_array()?
+            .iter()
+            .map(|v| v.as_str().map(String::from))
+            .collect()
+    }
+}
+
+fn default_number_of_teams() -> usize {
+    2
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleMatchmaker {
+    team_size: usize,
+    #[serde(default = "default_number_of_teams")]
+    number_of_teams: usize,
+    rules: Vec<Rule>,
+    #[serde(skip)]
+    entries: HashMap<EntryId, Entry>,
+}
+
+impl RuleMatchmaker {
+    pub fn new(team_size: usize, number_of_teams: usize, rules: Vec<Rule>) -> Result<Self, Box<dyn Error>> {
+        let matchmaker = Self {
+            team_size,
+            number_of_teams,
+            rules,
+            entries: HashMap::new(),
+        };
+        matchmaker.validate()?;
+        Ok(matchmaker)
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.team_size == 0 || self.number_of_teams == 0 {
+            return Err("Team size and number of teams must be positive integers".into());
+        }
+        for rule in &self.rules {
+            if let Rule::PartySize { min, max } = rule
+                && min > max
+            {
+                return Err(format!("Party size rule has min {} greater than max {}", min, max).into());
+            }
+        }
+        Ok(())
+    }
+
+    fn compatible(&self, a: &Entry, b: &Entry) -> bool {
+        self.rules.iter().all(|rule| rule.compatible(a, b))
+    }
+
+    /// Entries of a group must all share a value for every intersect rule, not just pairwise.
+    fn shares_values(&self, group: &[&Entry]) -> bool {
+        self.rules.iter().all(|rule| {
+            let Rule::Intersect { key } = rule else {
+                return true;
+            };
+            let mut sets = group.iter().filter_map(|entry| Rule::get_set(entry, key));
+            let Some(first) = sets.next() else {
+                return true;
+            };
+            !sets
+                .fold(first, |shared, set| &shared & &set)
+                .is_empty()
+        })
+    }
+
+    /// Used to balance teams, the first distance rule is treated as the skill value.
+    fn get_rating(&self, entry: &Entry) -> f64 {
+        self.rules
+            .iter()
+            .find_map(|rule| match rule {
+                Rule::Distance { key, .. } => Rule::get_number(entry, key),
+                _ => None,
+            })
+            .unwrap_or(0.0)
+    }
+
+    pub fn deserialize(value: Value) -> Result<Box<dyn Matchmaker + Send + Sync>, Box<dyn Error>> {
+        let matchmaker: RuleMatchmaker = serde_json::from_value(value)?;
+        matchmaker.validate()?;
+        Ok(Box::new(matchmaker))
+    }
+}
+
+impl Matchmaker for RuleMatchmaker {
+    fn get_type_name(&self) -> String {
+        String::from("rules")
+    }
+
+    fn metadata_schema(&self) -> Value {
+        let mut properties = Map::new();
+        let mut required: Vec<&String> = Vec::new();
+
+        for (key, schema) in self.rules.iter().filter_map(Rule::metadata_schema) {
+            // Several rules may read the same key
+            match properties.get_mut(key) {
+                Some(existing) => *existing = json!({"allOf": [existing.take(), schema]}),
+                None => {
+                    properties.insert(key.clone(), schema);
+                    required.push(key);
+                }
+            }
+        }
+
+        json!({"type": "object", "required": required, "properties": properties})
+    }
+
+    fn matchmake(&self) -> MatchmakerResult {
+        let mut entries: Vec<&Entry> = self.entries.values().collect();
+        // Longest waiting entries are matched first
+        entries.sort_by_key(|entry| entry.time_queued);
+
+        let mut matches: Vec<MatchedTeams> = Vec::new();
+        let mut taken: HashSet<EntryId> = HashSet::new();
+
+        for (index, anchor) in entries.iter().enumerate() {
+            if taken.contains(&anchor.id) {
+                continue;
+            }
+            let mut group: Vec<&Entry> = vec![anchor];
+
+            for candidate in entries.iter().skip(index + 1) {
+                if taken.contains(&candidate.id) {
+                    continue;
+                }
+                if !group.iter().all(|member| self.compatible(member, candidate)) {
+                    continue;
+                }
+                group.push(candidate);
+                if !self.shares_values(&group) {
+                    group.pop();
+                }
+            }
+
+            let parties: Vec<Party> = group
+                .iter()
+                .map(|entry| Party::new(entry.players.len(), self.get_rating(entry)))
+                .collect();
+
+            let Some(selected) = select_parties(&parties, self.team_size, self.number_of_teams) else {
+                continue;
+            };
+            let selected_parties: Vec<Party> = selected.iter().map(|&i| parties[i]).collect();
+            let Some(teams) = balance_teams(&selected_parties, self.team_size, self.number_of_teams) else {
+                continue;
+            };
+
+            let teams: Vec<Vec<EntryId>> = teams
+                .into_iter()
+                .map(|team| team.into_iter().map(|i| group[selected[i]].id).collect())
+                .collect();
+
+            taken.extend(teams.iter().flatten().copied());
+            matches.push(teams.into());
+        }
+
+        if matches.is_empty() {
+            return Skip(String::from("No entries satisfy every rule"));
+        }
+        Matched(matches)
+    }
+
+    fn serialize(&self) -> Result<Value, Box<dyn Error>> {
+        serde_json::to_value(self).map_err(|x| x.into())
+    }
+
+    fn remove_all(&mut self) -> Vec<Entry> {
+        self.entries.drain().map(|(_, v)| v).collect()
+    }
+
+    fn get_entries(&self) -> Vec<&Entry> {
+        self.entries.values().collect()
+    }
+
+    fn remove_entry(&mut self, entry_id: &EntryId) -> Result<Entry, MatchmakerError> {
+        self.entries
+            .remove(entry_id)
+            .ok_or(MatchmakerError::EntryNotFound)
+    }
+
+    fn get_entry(&self, entry_id: &EntryId) -> Option<&Entry> {
+        self.entries.get(entry_id)
+    }
+
+    fn add_entry(&mut self, entry: Entry) -> Result<(), MatchmakerError> {
+        if entry.players.is_empty() || entry.players.len() > self.team_size {
+            return Err(MatchmakerError::WrongTeamSize(String::from("Entry has wrong team size")));
+        }
+        for rule in &self.rules {
+            rule.validate(&entry)?;
+        }
+
+        self.entries.insert(entry.id, entry);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::MetadataSchema;
+    use uuid::Uuid;
+
+    fn matchmaker(rules: Value) -> RuleMatchmaker {
+        let settings = json!({"teamSize": 1, "numberOfTeams": 2, "rules": rules});
+        serde_json::from_value(settings).unwrap()
+    }
+
+    fn entry(metadata: Value) -> Entry {
+        Entry::new(Uuid::new_v4(), vec![Uuid::new_v4()], serde_json::from_value(metadata).unwrap())
+    }
+
+    #[test]
+    fn test_metadata_schema() {
+        let matchmaker = matchmaker(json!([
+            {"type": "equal", "key": "mode"},
+            {"type": "distance", "key": "elo", "maxDistance": 100},
+            {"type": "intersect", "key": "maps"},
+        ]));
+        let schema = MetadataSchema::new(matchmaker.metadata_schema(), None).unwrap();
+
+        let valid = entry(json!({"mode": "ranked", "elo": 1000, "maps": ["dust"]}));
+        assert!(schema.validate(&valid.metadata).is_ok());
+
+        let invalid = entry(json!({"mode": "ranked", "elo": "high", "maps": [1]}));
+        let error = schema.validate(&invalid.metadata).unwrap_err();
+        let paths: Vec<&str> = error.errors.iter().map(|x| x.path.as_str()).collect();
+        assert!(paths.contains(&"/elo"));
+        assert!(paths.contains(&"/maps/0"));
+    }
+
+    #[test]
+    fn test_rules_from_json() {
+        let matchmaker = matchmaker(json!([
+            {"type": "equal", "key": "mode"},
+            {"type": "distance", "key": "elo", "maxDistance": 100, "scalingFactor": 5},
+            {"type": "intersect", "key": "maps"},
+            {"type": "partySize", "min": 1, "max": 1},
+        ]));
+
+        assert_eq!(matchmaker.rules.len(), 4);
+        assert_eq!(
+            matchmaker.rules[1],
+            Rule::Distance {
+                key: String::from("elo"),
+                max_distance: 100.0,
+                scaling_factor: 5.0,
+                max_limit: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_missing_metadata_rejected() {
+        let mut matchmaker = matchmaker(json!([{"type": "distance", "key": "elo", "maxDistance": 100}]));
+
+        assert!(matchmaker.add_entry(entry(json!({"elo": "high"}))).is_err());
+        assert!(matchmaker.add_entry(entry(json!({"elo": 1000}))).is_ok());
+    }
+
+    #[test]
+    fn test_equal_rule() {
+        let mut matchmaker = matchmaker(json!([{"type": "equal", "key": "mode"}]));
+
+        matchmaker.add_entry(entry(json!({"mode": "ctf"}))).unwrap();
+        matchmaker.add_entry(entry(json!({"mode": "tdm"}))).unwrap();
+        assert!(matchmaker.matchmake().is_skip());
+
+        matchmaker.add_entry(entry(json!({"mode": "tdm"}))).unwrap();
+        assert!(matchmaker.matchmake().is_matched());
+    }
+
+    #[test]
+    fn test_distance_rule() {
+        let mut matchmaker = matchmaker(json!([{"type": "distance", "key": "elo", "maxDistance": 100}]));
+
+        matchmaker.add_entry(entry(json!({"elo": 1000}))).unwrap();
+        matchmaker.add_entry(entry(json!({"elo": 1200}))).unwrap();
+        assert!(matchmaker.matchmake().is_skip());
+
+        matchmaker.add_entry(entry(json!({"elo": 1100}))).unwrap();
+        assert!(matchmaker.matchmake().is_matched());
+    }
+
+    #[test]
+    fn test_intersect_rule_across_group() {
+        let mut matchmaker = RuleMatchmaker::new(
+            1,
+            3,
+            vec![Rule::Intersect {
+                key: String::from("maps"),
+            }],
+        )
+        .unwrap();
+
+        // Every pair overlaps, but no map is shared by all three
+        matchmaker.add_entry(entry(json!({"maps": ["a", "b"]}))).unwrap();
+        matchmaker.add_entry(entry(json!({"maps": ["b", "c"]}))).unwrap();
+        matchmaker.add_entry(entry(json!({"maps": ["c", "a"]}))).unwrap();
+        assert!(matchmaker.matchmake().is_skip());
+
+        matchmaker.add_entry(entry(json!({"maps": ["a", "b", "c"]}))).unwrap();
+        assert!(matchmaker.matchmake().is_matched());
+    }
+
+    #[test]
+    fn test_party_size_rule() {
+        let mut matchmaker = RuleMatchmaker::new(2, 2, vec![Rule::PartySize { min: 2, max: 2 }]).unwrap();
+
+        let solo = Entry::new(Uuid::new_v4(), vec![Uuid::new_v4()], Map::new());
+        let duo = Entry::new(Uuid::new_v4(), vec![Uuid::new_v4(), Uuid::new_v4()], Map::new());
+
+        assert!(matchmaker.add_entry(solo).is_err());
+        assert!(matchmaker.add_entry(duo).is_ok());
+    }
+
+    #[test]
+    fn test_party_size_min_above_max_rejected() {
+        assert!(RuleMatchmaker::new(2, 2, vec![Rule::PartySize { min: 3, max: 2 }]).is_err());
+
+        let settings = json!({"teamSize": 2, "rules": [{"type": "partySize", "min": 2, "max": 1}]});
+        assert!(RuleMatchmaker::deserialize(settings).is_err());
+    }
+}
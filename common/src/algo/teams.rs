@@ -0,0 +1,275 @@
+/// Upper bound on the number of search steps spent on a single selection or split, so a
+/// large candidate pool can't stall a tick. The best result found so far is used.
+const SEARCH_BUDGET: usize = 50_000;
+
+/// A party that has to be placed on a single team.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Party {
+    pub size: usize,
+    pub rating: f64,
+}
+
+impl Party {
+    pub fn new(size: usize, rating: f64) -> Self {
+        Self { size, rating }
+    }
+
+    fn weight(&self) -> f64 {
+        self.size as f64 * self.rating
+    }
+}
+
+/// Picks parties, preferring earlier ones, until `num_teams` teams of exactly `team_size`
+/// players can be filled. The first party is always part of the selection.
+///
+/// Returns the indices of the picked parties, or `None` if no selection fills every team.
+pub fn select_parties(parties: &[Party], team_size: usize, num_teams: usize) -> Option<Vec<usize>> {
+    if parties.is_empty() || team_size == 0 || num_teams == 0 {
+        return None;
+    }
+
+    let mut remaining_players = vec![0; parties.len() + 1];
+    for index in (0..parties.len()).rev() {
+        remaining_players[index] = remaining_players[index + 1] + parties[index].size;
+    }
+
+    let mut capacities = vec![team_size; num_teams];
+    let mut picked = Vec::new();
+    let mut budget = SEARCH_BUDGET;
+
+    if select(
+        parties,
+        &remaining_players,
+        0,
+        &mut capacities,
+        &mut picked,
+        &mut budget,
+    ) {
+        Some(picked)
+    } else {
+        None
+    }
+}
+
+fn select(
+    parties: &[Party],
+    remaining_players: &[usize],
+    index: usize,
+    capacities: &mut Vec<usize>,
+    picked: &mut Vec<usize>,
+    budget: &mut usize,
+) -> bool {
+    let open: usize = capacities.iter().sum();
+    if open == 0 {
+        return true;
+    }
+    if index >= parties.len() || remaining_players[index] < open || *budget == 0 {
+        return false;
+    }
+    *budget -= 1;
+
+    let size = parties[index].size;
+    let mut tried: Vec<usize> = Vec::new();
+
+    for team in 0..capacities.len() {
+        let capacity = capacities[team];
+        // Teams with the same free space are interchangeable
+        if capacity < size || tried.contains(&capacity) {
+            continue;
+        }
+        tried.push(capacity);
+
+        capacities[team] -= size;
+        picked.push(index);
+        if select(parties, remaining_players, index + 1, capacities, picked, budget) {
+            return true;
+        }
+        picked.pop();
+        capacities[team] += size;
+    }
+
+    // The first party is the one being matched, it can't be left out
+    if index == 0 {
+        return false;
+    }
+    select(parties, remaining_players, index + 1, capacities, picked, budget)
+}
+
+/// Splits all `parties` into `num_teams` teams of exactly `team_size` players, keeping
+/// parties together and minimising the gap between the highest and lowest rated team.
+///
+/// Returns the party indices of every team, or `None` if the parties can't fill the teams.
+pub fn balance_teams(parties: &[Party], team_size: usize, num_teams: usize) -> Option<Vec<Vec<usize>>> {
+    let total: usize = parties.iter().map(|p| p.size).sum();
+    if num_teams == 0 || total != team_size * num_teams {
+        return None;
+    }
+
+    // Placing large parties first prunes infeasible branches early
+    let mut order: Vec<usize> = (0..parties.len()).collect();
+    order.sort_by(|&a, &b| {
+        parties[b]
+            .size
+            .cmp(&parties[a].size)
+            .then(parties[b].rating.total_cmp(&parties[a].rating))
+    });
+
+    let mut search = BalanceSearch {
+        parties,
+        order,
+        capacities: vec![team_size; num_teams],
+        sums: vec![0.0; num_teams],
+        assignment: vec![0; parties.len()],
+        best: None,
+        budget: SEARCH_BUDGET,
+    };
+    let remaining: f64 = parties.iter().map(Party::weight).sum();
+    search.run(0, remaining);
+
+    let (_, assignment) = search.best?;
+    let mut teams = vec![Vec::new(); num_teams];
+    for (party, team) in assignment.into_iter().enumerate() {
+        teams[team].push(party);
+    }
+    Some(teams)
+}
+
+/// Rating gap between the highest and lowest rated team, in average rating per player.
+pub fn rating_spread(parties: &[Party], teams: &[Vec<usize>]) -> f64 {
+    let averages: Vec<f64> = teams
+        .iter()
+        .map(|team| {
+            let players: usize = team.iter().map(|&i| parties[i].size).sum();
+            let weight: f64 = team.iter().map(|&i| parties[i].weight()).sum();
+            if players == 0 { 0.0 } else { weight / players as f64 }
+        })
+        .collect();
+
+    let max = averages.iter().copied().fold(f64::MIN, f64::max);
+    let min = averages.iter().copied().fold(f64::MAX, f64::min);
+    max - min
+}
+
+struct BalanceSearch<'a> {
+    parties: &'a [Party],
+    order: Vec<usize>,
+    capacities: Vec<usize>,
+    sums: Vec<f64>,
+    assignment: Vec<usize>,
+    best: Option<(f64, Vec<usize>)>,
+    budget: usize,
+}
+
+impl BalanceSearch<'_> {
+    fn spread(&self) -> (f64, f64) {
+        let max = self.sums.iter().copied().fold(f64::MIN, f64::max);
+        let min = self.sums.iter().copied().fold(f64::MAX, f64::min);
+        (max, min)
+    }
+
+    fn run(&mut self, depth: usize, remaining: f64) {
+        if self.budget == 0 {
+            return;
+        }
+        self.budget -= 1;
+
+        let (max, min) = self.spread();
+        if depth == self.order.len() {
+            let spread = max - min;
+            if self.best.as_ref().is_none_or(|(best, _)| spread < *best) {
+                self.best = Some((spread, self.assignment.clone()));
+            }
+            return;
+        }
+
+        // Even if every remaining party joined the weakest team the gap can't beat the best
+        if let Some((best, _)) = &self.best
+            && max - (min + remaining) >= *best
+        {
+            return;
+        }
+
+        let party_index = self.order[depth];
+        let party = self.parties[party_index];
+
+        for team in 0..self.capacities.len() {
+            if self.capacities[team] < party.size {
+                continue;
+            }
+            let interchangeable = (0..team).any(|other| {
+                self.capacities[other] == self.capacities[team] && self.sums[other] == self.sums[team]
+            });
+            if interchangeable {
+                continue;
+            }
+
+            self.capacities[team] -= party.size;
+            self.sums[team] += party.weight();
+            self.assignment[party_index] = team;
+
+            self.run(depth + 1, remaining - party.weight());
+
+            self.capacities[team] += party.size;
+            self.sums[team] -= party.weight();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_parties_skips_unfitting() {
+        let parties = vec![
+            Party::new(3, 1000.0),
+            Party::new(3, 1000.0),
+            Party::new(3, 1000.0),
+            Party::new(2, 1000.0),
+            Party::new(2, 1000.0),
+        ];
+
+        // 3 + 2 per team, the third party of three can't fit
+        assert_eq!(select_parties(&parties, 5, 2), Some(vec![0, 1, 3, 4]));
+    }
+
+    #[test]
+    fn test_select_parties_not_enough_players() {
+        let parties = vec![Party::new(2, 1000.0), Party::new(1, 1000.0)];
+
+        assert_eq!(select_parties(&parties, 2, 2), None);
+    }
+
+    #[test]
+    fn test_balance_teams_minimises_gap() {
+        let parties = vec![
+            Party::new(1, 1000.0),
+            Party::new(1, 1100.0),
+            Party::new(1, 1200.0),
+            Party::new(1, 1300.0),
+        ];
+
+        let teams = balance_teams(&parties, 2, 2).unwrap();
+
+        assert_eq!(rating_spread(&parties, &teams), 0.0);
+    }
+
+    #[test]
+    fn test_balance_teams_keeps_parties_together() {
+        let parties = vec![
+            Party::new(2, 1500.0),
+            Party::new(1, 1000.0),
+            Party::new(1, 1400.0),
+            Party::new(2, 1200.0),
+        ];
+
+        let teams = balance_teams(&parties, 3, 2).unwrap();
+
+        for team in &teams {
+            let players: usize = team.iter().map(|&i| parties[i].size).sum();
+            assert_eq!(players, 3);
+        }
+        // (2 * 1500 + 1000) vs (2 * 1200 + 1400)
+        assert!((rating_spread(&parties, &teams) - 200.0 / 3.0).abs() < 1e-9);
+    }
+}
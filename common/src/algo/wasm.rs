@@ -0,0 +1,447 @@
+use crate::entry::{Entry, EntryId};
+use crate::matchmaker::MatchmakerResult::{Matched, Skip};
+use crate::matchmaker::{MatchedTeams, Matchmaker, MatchmakerError, MatchmakerResult};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::ops::Sub;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+use uuid::Uuid;
+use wasmi::{Config, Engine, Instance, Linker, Module, Store};
+
+fn default_fuel() -> u64 {
+    10_000_000
+}
+
+/// Directory WASM modules are loaded from, `WASM_MODULE_DIR` or `modules` in the working
+/// directory. Queue settings are posted over HTTP, so modules anywhere else are refused.
+fn module_dir() -> PathBuf {
+    PathBuf::from(std::env::var("WASM_MODULE_DIR").unwrap_or_else(|_| String::from("modules")))
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct WasmSettings {
+    /// Path of the `.wasm` file implementing the matchmaker, relative to the module directory
+    path: String,
+    /// Passed to the module untouched on every call
+    #[serde(default)]
+    settings: Value,
+    /// Instructions a single call may execute before it is aborted
+    #[serde(default = "default_fuel")]
+    fuel: u64,
+}
+
+/// Entry as seen by a WASM module.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WasmEntry<'a> {
+    id: EntryId,
+    players: &'a Vec<Uuid>,
+    metadata: &'a Map<String, Value>,
+    seconds_queued: f64,
+}
+
+impl<'a> From<&'a Entry> for WasmEntry<'a> {
+    fn from(entry: &'a Entry) -> Self {
+        Self {
+            id: entry.id,
+            players: &entry.players,
+            metadata: &entry.metadata,
+            seconds_queued: chrono::Utc::now().sub(entry.time_queued).as_seconds_f64(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct WasmRequest<'a> {
+    settings: &'a Value,
+    entries: Vec<WasmEntry<'a>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WasmMatch {
+    teams: Vec<Vec<EntryId>>,
+    #[serde(default)]
+    roles: HashMap<Uuid, String>,
+    #[serde(default)]
+    region: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum WasmResponse {
+    Matched(Vec<WasmMatch>),
+    Skip(String),
+    Error {
+        message: String,
+        #[serde(default)]
+        entry: Option<EntryId>,
+    },
+}
+
+/// Matchmaker implemented by a WebAssembly module, so custom logic can be added without
+/// rebuilding the server. The module can't import anything and must export:
+///
+/// - `memory`
+/// - `alloc(len: i32) -> i32`, returning a pointer to `len` writable bytes
+/// - `matchmake(ptr: i32, len: i32) -> i64`, receiving the JSON request
+///   `{"settings": ..., "entries": [{"id", "players", "metadata", "secondsQueued"}]}` and
+///   returning `{"matched": [{"teams": [[entry ids]], "roles"?, "region"?}]}`,
+///   `{"skip": "reason"}` or `{"error": {"message": "...", "entry"?: id}}`
+///
+/// It may also export `validate(ptr: i32, len: i32) -> i64`, receiving a single entry and
+/// returning an empty response to accept it or `{"error": {"message": "..."}}` to reject it.
+///
+/// Responses are returned as the pointer in the upper 32 bits and the length in the lower 32.
+/// The instance is kept between calls, so the module has to free what `alloc` handed out.
+pub struct WasmMatchmaker {
+    settings: WasmSettings,
+    engine: Engine,
+    module: Module,
+    /// Instance reused by every call, dropped after a call fails
+    instance: Mutex<Option<(Store<()>, Instance)>>,
+    entries: HashMap<EntryId, Entry>,
+}
+
+impl WasmMatchmaker {
+    /// Loads the module at `settings.path` inside `dir`.
+    fn new(settings: WasmSettings, dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let not_found =
+            |err: &dyn std::fmt::Display| format!("Failed to read WASM module {}: {}", settings.path, err);
+        let dir = dir.canonicalize().map_err(|err| not_found(&err))?;
+        let path = dir.join(&settings.path).canonicalize().map_err(|err| not_found(&err))?;
+        if !path.starts_with(&dir) {
+            return Err(format!("WASM module {} is outside of {}", settings.path, dir.display()).into());
+        }
+
+        let bytes = std::fs::read(&path).map_err(|err| not_found(&err))?;
+
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &bytes)?;
+
+        for export in ["alloc", "matchmake"] {
+            if module.get_export(export).is_none() {
+                return Err(format!("WASM module {} does not export '{}'", settings.path, export).into());
+            }
+        }
+
+        Ok(Self {
+            settings,
+            engine,
+            module,
+            instance: Mutex::new(None),
+            entries: HashMap::new(),
+        })
+    }
+
+    fn instantiate(&self) -> Result<(Store<()>, Instance), Box<dyn Error>> {
+        let mut store = Store::new(&self.engine, ());
+        store
+            .set_fuel(self.settings.fuel)
+            .map_err(|err| err.to_string())?;
+
+        let instance = Linker::<()>::new(&self.engine)
+            .instantiate(&mut store, &self.module)?
+            .start(&mut store)?;
+        Ok((store, instance))
+    }
+
+    /// Calls `export` with `input`. Returns `None` if the module doesn't export the function.
+    /// A call that fails, e.g. a trap or running out of fuel, may leave the instance in any
+    /// state, so the next call starts from a new one.
+    fn call(&self, export: &str, input: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let mut cached = self.instance.lock().unwrap_or_else(|err| err.into_inner());
+        let (mut store, instance) = match cached.take() {
+            Some(cached) => cached,
+            None => self.instantiate()?,
+        };
+
+        let output = Self::call_instance(&mut store, instance, self.settings.fuel, export, input)?;
+        *cached = Some((store, instance));
+        Ok(output)
+    }
+
+    fn call_instance(
+        store: &mut Store<()>,
+        instance: Instance,
+        fuel: u64,
+        export: &str,
+        input: &[u8],
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        store.set_fuel(fuel).map_err(|err| err.to_string())?;
+
+        let Ok(function) = instance.get_typed_func::<(i32, i32), i64>(&*store, export) else {
+            return Ok(None);
+        };
+        let memory = instance
+            .get_memory(&*store, "memory")
+            .ok_or("WASM module does not export 'memory'")?;
+        let alloc = instance.get_typed_func::<i32, i32>(&*store, "alloc")?;
+
+        let len = i32::try_from(input.len())?;
+        let ptr = alloc.call(&mut *store, len)?;
+        memory
+            .write(&mut *store, ptr as u32 as usize, input)
+            .map_err(|err| err.to_string())?;
+
+        let packed = function.call(&mut *store, (ptr, len))? as u64;
+        let (output_ptr, output_len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+
+        // Check the module's output fits in its memory before allocating a buffer for it
+        let output = output_ptr
+            .checked_add(output_len)
+            .and_then(|end| memory.data(&*store).get(output_ptr..end))
+            .ok_or_else(|| format!("WASM module returned {} bytes at {} outside of its memory", output_len, output_ptr))?;
+
+        Ok(Some(output.to_vec()))
+    }
+
+    /// Turns the module's matches into a result. Matches with unknown or repeated entries are
+    /// a bug in the module rather than in any entry, so the whole tick is skipped.
+    fn check_matches(&self, matches: Vec<WasmMatch>) -> MatchmakerResult {
+        let mut seen: HashSet<EntryId> = HashSet::new();
+
+        for id in matches.iter().flat_map(|x| x.teams.iter().flatten()) {
+            if !self.entries.contains_key(id) || !seen.insert(*id) {
+                warn!("WASM module {} returned unknown or repeated entry {:?}", self.settings.path, id);
+                return Skip(String::from("WASM module returned an invalid match"));
+            }
+        }
+
+        if matches.is_empty() {
+            return Skip(String::from("No teams found"));
+        }
+        Matched(
+            matches
+                .into_iter()
+                .map(|x| MatchedTeams {
+                    teams: x.teams,
+                    roles: x.roles,
+                    region: x.region,
+                })
+                .collect(),
+        )
+    }
+
+    pub fn deserialize(value: Value) -> Result<Box<dyn Matchmaker + Send + Sync>, Box<dyn Error>> {
+        let settings: WasmSettings = serde_json::from_value(value)?;
+        Ok(Box::new(Self::new(settings, &module_dir())?))
+    }
+}
+
+impl Matchmaker for WasmMatchmaker {
+    fn get_type_name(&self) -> String {
+        String::from("wasm")
+    }
+
+    fn matchmake(&self) -> MatchmakerResult {
+        let request = WasmRequest {
+            settings: &self.settings.settings,
+            entries: self.entries.values().map(WasmEntry::from).collect(),
+        };
+
+        let response = serde_json::to_vec(&request)
+            .map_err(|err| err.to_string())
+            .and_then(|input| self.call("matchmake", &input).map_err(|err| err.to_string()))
+            .and_then(|output| {
+                serde_json::from_slice::<WasmResponse>(&output.unwrap_or_default())
+                    .map_err(|err| format!("Invalid response from WASM module: {}", err))
+            });
+
+        // Only an entry the module blames is removed, any other failure leaves the queue as is
+        match response {
+            Ok(WasmResponse::Matched(matches)) => self.check_matches(matches),
+            Ok(WasmResponse::Skip(reason)) => Skip(reason),
+            Ok(WasmResponse::Error {
+                message,
+                entry: Some(entry),
+            }) if self.entries.contains_key(&entry) => MatchmakerResult::Error(message, Some(entry)),
+            Ok(WasmResponse::Error { message, .. }) => {
+                warn!("WASM module {} failed: {}", self.settings.path, message);
+                Skip(message)
+            }
+            Err(err) => {
+                warn!("WASM module {} failed: {}", self.settings.path, err);
+                Skip(err)
+            }
+        }
+    }
+
+    fn serialize(&self) -> Result<Value, Box<dyn Error>> {
+        serde_json::to_value(&self.settings).map_err(|x| x.into())
+    }
+
+    fn remove_all(&mut self) -> Vec<Entry> {
+        self.entries.drain().map(|(_, v)| v).collect()
+    }
+
+    fn get_entries(&self) -> Vec<&Entry> {
+        self.entries.values().collect()
+    }
+
+    fn remove_entry(&mut self, entry_id: &EntryId) -> Result<Entry, MatchmakerError> {
+        self.entries
+            .remove(entry_id)
+            .ok_or(MatchmakerError::EntryNotFound)
+    }
+
+    fn get_entry(&self, entry_id: &EntryId) -> Option<&Entry> {
+        self.entries.get(entry_id)
+    }
+
+    fn add_entry(&mut self, entry: Entry) -> Result<(), MatchmakerError> {
+        let internal = |err: &dyn std::fmt::Display| MatchmakerError::Internal(err.to_string());
+        let input = serde_json::to_vec(&WasmEntry::from(&entry)).map_err(|err| internal(&err))?;
+
+        if let Some(output) = self.call("validate", &input).map_err(|err| internal(&err))?
+            && !output.is_empty()
+            && let WasmResponse::Error { message, .. } =
+                serde_json::from_slice(&output).map_err(|err| internal(&err))?
+        {
+            return Err(MatchmakerError::EntryRejected(message));
+        }
+
+        self.entries.insert(entry.id, entry);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Loads the module `path` from the temporary directory.
+    fn load(settings: Value) -> WasmMatchmaker {
+        WasmMatchmaker::new(serde_json::from_value(settings).unwrap(), &std::env::temp_dir()).unwrap()
+    }
+
+    /// Writes a module whose `matchmake` always returns `response` to the temporary directory.
+    fn module(response: &str, matchmake: Option<&str>) -> String {
+        let escaped = response.replace('"', "\\\"");
+        let matchmake = matchmake
+            .map(String::from)
+            .unwrap_or_else(|| format!("(i64.const {})", response.len()));
+
+        let wat = format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (global $next (mut i32) (i32.const 4096))
+                (data (i32.const 0) "{escaped}")
+                (func (export "alloc") (param $len i32) (result i32)
+                    (local $ptr i32)
+                    (local.set $ptr (global.get $next))
+                    (global.set $next (i32.add (global.get $next) (local.get $len)))
+                    (local.get $ptr))
+                (func (export "matchmake") (param i32 i32) (result i64)
+                    {matchmake}))"#
+        );
+
+        let name = format!("{}.wasm", Uuid::new_v4());
+        std::fs::write(std::env::temp_dir().join(&name), wat::parse_str(wat).unwrap()).unwrap();
+        name
+    }
+
+    fn entry(id: u128) -> Entry {
+        Entry::new(Uuid::from_u128(id), vec![Uuid::new_v4()], Map::new())
+    }
+
+    #[test]
+    fn test_missing_module_rejected() {
+        let settings = json!({"path": "does-not-exist.wasm"});
+
+        assert!(WasmMatchmaker::deserialize(settings).is_err());
+    }
+
+    #[test]
+    fn test_module_outside_directory_rejected() {
+        let path = module(r#"{"skip":"waiting"}"#, None);
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory).unwrap();
+        let settings: WasmSettings = serde_json::from_value(json!({ "path": format!("../{}", path) })).unwrap();
+
+        assert!(WasmMatchmaker::new(settings, &directory).is_err());
+    }
+
+    #[test]
+    fn test_skip() {
+        let path = module(r#"{"skip":"waiting"}"#, None);
+        let matchmaker = load(json!({ "path": path }));
+
+        assert_eq!(matchmaker.matchmake(), Skip(String::from("waiting")));
+    }
+
+    #[test]
+    fn test_matched() {
+        let first = Uuid::from_u128(1);
+        let second = Uuid::from_u128(2);
+        let path = module(
+            &format!(r#"{{"matched":[{{"teams":[["{first}"],["{second}"]]}}]}}"#),
+            None,
+        );
+        let mut matchmaker = load(json!({ "path": path }));
+
+        matchmaker.add_entry(entry(1)).unwrap();
+        matchmaker.add_entry(entry(2)).unwrap();
+
+        let result = matchmaker.matchmake();
+        let Matched(matches) = result else {
+            panic!("Expected a match, got {:?}", result);
+        };
+        assert_eq!(
+            matches[0].teams,
+            vec![vec![EntryId(first)], vec![EntryId(second)]]
+        );
+    }
+
+    #[test]
+    fn test_unknown_entry_skips() {
+        let unknown = Uuid::from_u128(3);
+        let path = module(&format!(r#"{{"matched":[{{"teams":[["{unknown}"]]}}]}}"#), None);
+        let mut matchmaker = load(json!({ "path": path }));
+
+        matchmaker.add_entry(entry(1)).unwrap();
+
+        assert!(matchmaker.matchmake().is_skip());
+    }
+
+    #[test]
+    fn test_error_names_entry() {
+        let path = module(
+            &format!(r#"{{"error":{{"message":"bad","entry":"{}"}}}}"#, Uuid::from_u128(1)),
+            None,
+        );
+        let mut matchmaker = load(json!({ "path": path }));
+
+        matchmaker.add_entry(entry(1)).unwrap();
+
+        assert_eq!(
+            matchmaker.matchmake(),
+            MatchmakerResult::Error(String::from("bad"), Some(EntryId(Uuid::from_u128(1))))
+        );
+    }
+
+    #[test]
+    fn test_output_outside_memory_skips() {
+        // Claims 4 GiB of output at address 0, far beyond its single page of memory
+        let path = module("", Some("(i64.const 4294967295)"));
+        let matchmaker = load(json!({ "path": path }));
+
+        assert!(matchmaker.matchmake().is_skip());
+    }
+
+    #[test]
+    fn test_runaway_module_is_stopped() {
+        let path = module("", Some("(loop $forever (br $forever)) (i64.const 0)"));
+        let matchmaker = load(json!({ "path": path, "fuel": 10_000 }));
+
+        assert!(matchmaker.matchmake().is_skip());
+    }
+}
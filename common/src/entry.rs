@@ -7,12 +7,12 @@ use uuid::Uuid;
 #[derive(Eq, Hash, PartialEq)]
 pub struct EntryId(pub Uuid);
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Entry {
     pub id: EntryId,
     pub players: Vec<Uuid>,
-    #[serde(skip)]
+    #[serde(default = "Utc::now")]
     pub time_queued: DateTime<Utc>,
     pub metadata: Map<String, serde_json::Value>,
 }
@@ -29,6 +29,12 @@ pub struct GameFinderSettings {
     pub id_path: String,
     pub host_path: String,
     pub port_path: String,
+    #[serde(default = "default_region")]
+    pub default_region: String,
+}
+
+fn default_region() -> String {
+    String::from("default")
 }
 
 impl Default for GameFinderSettings {
@@ -41,6 +47,8 @@ impl Default for GameFinderSettings {
                 .unwrap_or_else(|_| "$.host".to_string()),
             port_path: std::env::var("GAMEFINDER_PORT_PATH")
                 .unwrap_or_else(|_| "$.port".to_string()),
+            default_region: std::env::var("GAMEFINDER_DEFAULT_REGION")
+                .unwrap_or_else(|_| default_region()),
         };
 
         info!("Game finder settings: {:?}", settings);
@@ -58,6 +66,12 @@ lazy_static! {
     static ref CLIENT: Client = Client::new();
 }
 
+impl Default for GameFinder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl GameFinder {
     pub fn new() -> GameFinder {
         GameFinder {
@@ -65,17 +79,30 @@ impl GameFinder {
         }
     }
 
+    /// Requests a game for `players` from the game finder. `{playlist}` and `{region}` in
+    /// the base url are replaced, using the default region if the match has none. Urls without
+    /// the `{region}` placeholder get the region as the `region` query parameter instead.
     pub async fn find_game(
         &self,
         playlist: &str,
         players: &Vec<Vec<Uuid>>,
+        region: Option<&str>,
     ) -> Result<Value, GameFinderError> {
-        let url = self.config.base_url.replace("{playlist}", playlist);
+        let region = region.unwrap_or(&self.config.default_region);
+        let has_placeholder = self.config.base_url.contains("{region}");
+        let url = self
+            .config
+            .base_url
+            .replace("{playlist}", playlist)
+            .replace("{region}", region);
 
         info!("Making game request to {}", url);
 
-        let response = CLIENT
-            .post(&url)
+        let mut request = CLIENT.post(&url);
+        if !has_placeholder {
+            request = request.query(&[("region", region)]);
+        }
+        let response = request
             .json(&players)
             .send()
             .await
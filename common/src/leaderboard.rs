@@ -0,0 +1,210 @@
+use crate::rating::{PlayerRating, REPORT_LOCK};
+use crate::schema;
+use crate::storage::{Storage, StorageError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use uuid::Uuid;
+
+/// Season of a queue that has never been reset
+pub const FIRST_SEASON: u32 = 1;
+
+/// Ratings of a queue are kept per season, a new season starts every player over at the
+/// default rating while the previous seasons' leaderboards stay readable.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Season {
+    pub queue: String,
+    pub number: u32,
+    /// When the season was started, `None` for the first season
+    pub started_at: Option<DateTime<Utc>>,
+}
+
+impl Season {
+    pub fn first(queue: &str) -> Self {
+        Self {
+            queue: String::from(queue),
+            number: FIRST_SEASON,
+            started_at: None,
+        }
+    }
+}
+
+/// Rating players are ranked by.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum RatingOrder {
+    Elo,
+    Glicko,
+}
+
+impl RatingOrder {
+    /// The rating the matchmaker with the metadata `schema` matches players by, Glicko-2 if it
+    /// requires a `rating` and Elo otherwise.
+    pub fn for_schema(schema: &Value) -> Self {
+        if schema::required_keys(schema).iter().any(|key| key == "rating") {
+            RatingOrder::Glicko
+        } else {
+            RatingOrder::Elo
+        }
+    }
+
+    pub fn score(self, rating: &PlayerRating) -> f64 {
+        match self {
+            RatingOrder::Elo => rating.elo,
+            RatingOrder::Glicko => rating.glicko.rating,
+        }
+    }
+
+    /// Highest score first, ties ordered by player id so pages don't overlap.
+    pub fn compare(self, a: &PlayerRating, b: &PlayerRating) -> Ordering {
+        self.score(b).total_cmp(&self.score(a)).then(a.player.cmp(&b.player))
+    }
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaderboardEntry {
+    /// Position on the leaderboard, starting at 1
+    pub rank: usize,
+    #[serde(flatten)]
+    pub rating: PlayerRating,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Leaderboard {
+    pub queue: String,
+    pub season: u32,
+    pub order: RatingOrder,
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+/// A page of the leaderboard of `queue` in `season`, the current season if `None`.
+pub fn get_leaderboard(
+    storage: &dyn Storage,
+    queue: &str,
+    season: Option<u32>,
+    order: RatingOrder,
+    offset: usize,
+    limit: usize,
+) -> Result<Leaderboard, StorageError> {
+    let season = match season {
+        Some(season) => season,
+        None => storage.current_season(queue)?.number,
+    };
+    let entries = storage
+        .get_leaderboard(queue, season, order, offset, limit)?
+        .into_iter()
+        .enumerate()
+        .map(|(index, rating)| LeaderboardEntry {
+            rank: offset + index + 1,
+            rating,
+        })
+        .collect();
+
+    Ok(Leaderboard {
+        queue: String::from(queue),
+        season,
+        order,
+        entries,
+    })
+}
+
+/// Position of `player` on the leaderboard of `queue` in `season`, the current season if `None`.
+/// `None` if the player has no reported match that season.
+pub fn get_rank(
+    storage: &dyn Storage,
+    queue: &str,
+    season: Option<u32>,
+    order: RatingOrder,
+    player: Uuid,
+) -> Result<Option<LeaderboardEntry>, StorageError> {
+    let season = match season {
+        Some(season) => season,
+        None => storage.current_season(queue)?.number,
+    };
+    Ok(storage
+        .get_rank(queue, season, order, player)?
+        .map(|(rank, rating)| LeaderboardEntry { rank, rating }))
+}
+
+/// Ends the current season of `queue`. Results reported afterwards rate players from scratch.
+pub fn start_season(storage: &dyn Storage, queue: &str) -> Result<Season, StorageError> {
+    // A result being reported keeps updating the season its ratings were read from
+    let _guard = REPORT_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    storage.start_season(queue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::Entry;
+    use crate::rating::MatchOutcome;
+    use crate::storage::MatchRecord;
+    use crate::storage::json::JsonStorage;
+    use serde_json::{Map, json};
+
+    fn rating(elo: f64, glicko: f64) -> PlayerRating {
+        let mut rating = PlayerRating::new(Uuid::new_v4(), "ranked");
+        rating.elo = elo;
+        rating.glicko.rating = glicko;
+        rating
+    }
+
+    #[test]
+    fn test_order_for_schema() {
+        let glicko = json!({"allOf": [{"required": ["rating", "rd", "volatility"]}]});
+        assert_eq!(RatingOrder::for_schema(&glicko), RatingOrder::Glicko);
+        assert_eq!(RatingOrder::for_schema(&json!({"required": ["elo"]})), RatingOrder::Elo);
+        assert_eq!(RatingOrder::for_schema(&json!({})), RatingOrder::Elo);
+    }
+
+    #[test]
+    fn test_compare() {
+        let (high, low) = (rating(1200.0, 1400.0), rating(1100.0, 1600.0));
+        assert_eq!(RatingOrder::Elo.compare(&high, &low), Ordering::Less);
+        assert_eq!(RatingOrder::Glicko.compare(&high, &low), Ordering::Greater);
+
+        let tied = PlayerRating {
+            player: Uuid::max(),
+            ..high.clone()
+        };
+        assert_eq!(RatingOrder::Elo.compare(&high, &tied), Ordering::Less);
+    }
+
+    #[test]
+    fn test_leaderboard_pages_and_seasons() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory).unwrap();
+        let storage = JsonStorage::new(directory);
+
+        let players: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let teams: Vec<Vec<Entry>> =
+            players.iter().map(|player| vec![Entry::new(Uuid::new_v4(), vec![*player], Map::new())]).collect();
+        let record = MatchRecord::new("ranked", &teams, json!({"address": "127.0.0.1:7777"}), None);
+        storage.save_match(&record).unwrap();
+        let outcome = MatchOutcome {
+            winner: None,
+            placements: Some(vec![1, 3, 2]),
+        };
+        crate::rating::report_result(&storage, record.id, &outcome).unwrap();
+
+        let page = get_leaderboard(&storage, "ranked", None, RatingOrder::Elo, 1, 10).unwrap();
+        assert_eq!(page.season, FIRST_SEASON);
+        let ranks: Vec<(usize, Uuid)> = page.entries.iter().map(|x| (x.rank, x.rating.player)).collect();
+        assert_eq!(ranks, vec![(2, players[2]), (3, players[1])]);
+
+        let entry = get_rank(&storage, "ranked", None, RatingOrder::Elo, players[1]).unwrap();
+        assert_eq!(entry.map(|x| x.rank), Some(3));
+
+        let season = start_season(&storage, "ranked").unwrap();
+        assert_eq!(season.number, FIRST_SEASON + 1);
+        assert!(get_leaderboard(&storage, "ranked", None, RatingOrder::Elo, 0, 10).unwrap().entries.is_empty());
+        assert!(get_rank(&storage, "ranked", None, RatingOrder::Elo, players[1]).unwrap().is_none());
+
+        let previous = get_leaderboard(&storage, "ranked", Some(FIRST_SEASON), RatingOrder::Elo, 0, 10).unwrap();
+        assert_eq!(previous.entries.len(), 3);
+    }
+}
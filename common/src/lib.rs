@@ -3,4 +3,9 @@ pub mod matchmaker;
 pub mod gamefinder;
 pub mod entry;
 pub mod queue_tracker;
+pub mod leaderboard;
 pub mod queue;
+pub mod profile;
+pub mod rating;
+pub mod schema;
+pub mod storage;
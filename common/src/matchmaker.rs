@@ -1,12 +1,119 @@
 use crate::algo::elo::EloMatchmaker;
 use crate::entry::{Entry, EntryId};
-use serde_json::Value;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
 use std::error::Error;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+use uuid::Uuid;
 use crate::algo::flexible::FlexibleMatchMaker;
+use crate::algo::glicko2::Glicko2Matchmaker;
+use crate::algo::regional::RegionalMatchmaker;
+use crate::algo::roles::RoleMatchmaker;
+use crate::algo::rules::RuleMatchmaker;
+use crate::algo::wasm::WasmMatchmaker;
+
+/// The teams of a single match, plus the role each player was assigned (if the
+/// matchmaker assigns roles) and the region the game should be hosted in.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct MatchedTeams {
+    pub teams: Vec<Vec<EntryId>>,
+    pub roles: HashMap<Uuid, String>,
+    pub region: Option<String>,
+}
+
+impl MatchedTeams {
+    pub fn new(teams: Vec<Vec<EntryId>>, roles: HashMap<Uuid, String>) -> Self {
+        Self {
+            teams,
+            roles,
+            region: None,
+        }
+    }
+}
+
+impl From<Vec<Vec<EntryId>>> for MatchedTeams {
+    fn from(teams: Vec<Vec<EntryId>>) -> Self {
+        Self::new(teams, HashMap::new())
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum MatchmakerError {
+    #[error("Entry not found")]
+    EntryNotFound,
+    #[error("{0}")]
+    WrongTeamSize(String),
+    #[error("{0}")]
+    InvalidMetadata(String),
+    /// Refused by matchmaker specific logic, e.g. a WASM module's `validate`
+    #[error("{0}")]
+    EntryRejected(String),
+    #[error("Matchmaker failed: {0}")]
+    Internal(String),
+}
+
+impl MatchmakerError {
+    /// Stable, machine-readable code clients can branch on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            MatchmakerError::EntryNotFound => "ENTRY_NOT_FOUND",
+            MatchmakerError::WrongTeamSize(_) => "WRONG_TEAM_SIZE",
+            MatchmakerError::InvalidMetadata(_) => "INVALID_METADATA",
+            MatchmakerError::EntryRejected(_) => "ENTRY_REJECTED",
+            MatchmakerError::Internal(_) => "MATCHMAKER_ERROR",
+        }
+    }
+}
+
+/// Why an entry is or isn't being matched, without changing anything in the queue.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Explanation {
+    pub entry: EntryId,
+    pub matchmaker: String,
+    pub summary: String,
+    /// Matchmaker specific state, e.g. the current Elo window
+    pub details: Value,
+    /// Nearest other entries and whether they could be matched with this entry
+    pub candidates: Vec<CandidateExplanation>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CandidateExplanation {
+    pub entry: EntryId,
+    pub accepted: bool,
+    pub reason: String,
+}
+
+impl CandidateExplanation {
+    pub fn accepted(entry: EntryId, reason: impl Into<String>) -> Self {
+        Self {
+            entry,
+            accepted: true,
+            reason: reason.into(),
+        }
+    }
+
+    pub fn rejected(entry: EntryId, reason: impl Into<String>) -> Self {
+        Self {
+            entry,
+            accepted: false,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Number of candidates listed in an [`Explanation`].
+pub const EXPLAIN_CANDIDATES: usize = 10;
 
 #[derive(PartialEq, Debug)]
 pub enum MatchmakerResult {
-    Matched(Vec<Vec<EntryId>>),
+    /// Every match formed in a single tick, no entry appears in more than one match.
+    Matched(Vec<MatchedTeams>),
     Skip(String),
     Error(String, Option<EntryId>),
 }
@@ -45,26 +152,124 @@ pub trait Matchmaker: Send + Sync {
     fn get_type_name(&self) -> String;
     fn matchmake(&self) -> MatchmakerResult;
 
+    /// JSON Schema every entry's metadata must match to join a queue using this matchmaker.
+    fn metadata_schema(&self) -> Value {
+        json!({"type": "object"})
+    }
+
     fn serialize(&self) -> Result<Value, Box<dyn Error>>;
 
     fn remove_all(&mut self) -> Vec<Entry>;
 
     fn get_entries(&self) -> Vec<&Entry>;
 
-    fn remove_entry(&mut self, entry_id: &EntryId) -> Result<Entry, Box<dyn Error>>;
+    fn remove_entry(&mut self, entry_id: &EntryId) -> Result<Entry, MatchmakerError>;
 
     fn get_entry(&self, entry_id: &EntryId) -> Option<&Entry>;
 
-    fn add_entry(&mut self, entry: Entry) -> Result<(), Box<dyn Error>>;
+    fn add_entry(&mut self, entry: Entry) -> Result<(), MatchmakerError>;
+
+    /// Explains why an entry is or isn't being matched. Matchmakers without detailed
+    /// diagnostics only report that the entry is waiting.
+    fn explain(&self, entry_id: &EntryId) -> Result<Explanation, MatchmakerError> {
+        self.get_entry(entry_id).ok_or(MatchmakerError::EntryNotFound)?;
+
+        Ok(Explanation {
+            entry: *entry_id,
+            matchmaker: self.get_type_name(),
+            summary: String::from("Entry is waiting, this matchmaker has no detailed diagnostics"),
+            details: Value::Null,
+            candidates: Vec::new(),
+        })
+    }
+}
+
+/// Builds a matchmaker from its serialized settings.
+pub type MatchmakerFactory =
+    Arc<dyn Fn(Value) -> Result<Box<dyn Matchmaker + Send + Sync>, Box<dyn Error>> + Send + Sync>;
+
+lazy_static! {
+    static ref REGISTRY: RwLock<HashMap<String, MatchmakerFactory>> = {
+        let mut registry: HashMap<String, MatchmakerFactory> = HashMap::new();
+        registry.insert(String::from("elo"), Arc::new(EloMatchmaker::deserialize));
+        registry.insert(String::from("flexible"), Arc::new(FlexibleMatchMaker::deserialize));
+        registry.insert(String::from("glicko2"), Arc::new(Glicko2Matchmaker::deserialize));
+        registry.insert(String::from("roles"), Arc::new(RoleMatchmaker::deserialize));
+        registry.insert(String::from("regional"), Arc::new(RegionalMatchmaker::deserialize));
+        registry.insert(String::from("rules"), Arc::new(RuleMatchmaker::deserialize));
+        registry.insert(String::from("wasm"), Arc::new(WasmMatchmaker::deserialize));
+        RwLock::new(registry)
+    };
+}
+
+/// Registers a matchmaker type, so queues can be created with `name` as their matchmaker.
+/// Fails if a matchmaker with the same name is already registered.
+pub fn register<F>(name: &str, factory: F) -> Result<(), Box<dyn Error>>
+where
+    F: Fn(Value) -> Result<Box<dyn Matchmaker + Send + Sync>, Box<dyn Error>> + Send + Sync + 'static,
+{
+    let mut registry = REGISTRY.write().map_err(|_| "Matchmaker registry is poisoned")?;
+
+    if registry.contains_key(name) {
+        return Err(format!("Matchmaker type {} is already registered", name).into());
+    }
+    registry.insert(String::from(name), Arc::new(factory));
+    Ok(())
+}
+
+/// Names of every registered matchmaker type.
+pub fn registered_types() -> Vec<String> {
+    let Ok(registry) = REGISTRY.read() else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = registry.keys().cloned().collect();
+    names.sort();
+    names
 }
 
 pub fn deserialize(
     name: String,
     value: Value,
 ) -> Result<Box<dyn Matchmaker + Send + Sync>, Box<dyn Error>> {
-    match name.as_str() {
-        "elo" => EloMatchmaker::deserialize(value),
-        "flexible" => FlexibleMatchMaker::deserialize(value),
-        _ => Err(format!("Unknown matchmaker type: {}", name).into()),
+    // Factories may deserialize other matchmakers, so the lock is released before calling
+    let factory = REGISTRY
+        .read()
+        .map_err(|_| "Matchmaker registry is poisoned")?
+        .get(&name)
+        .cloned()
+        .ok_or_else(|| format!("Unknown matchmaker type: {}", name))?;
+
+    factory(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_types_registered() {
+        let types = registered_types();
+
+        for name in ["elo", "flexible", "glicko2", "regional", "roles", "rules", "wasm"] {
+            assert!(types.contains(&String::from(name)));
+        }
+    }
+
+    #[test]
+    fn test_unknown_type() {
+        assert!(deserialize(String::from("does-not-exist"), json!({})).is_err());
+    }
+
+    #[test]
+    fn test_register_custom_type() {
+        register("custom-elo", EloMatchmaker::deserialize).unwrap();
+
+        let settings = json!({"scalingFactor": 1.0, "teamSize": 1, "maxSkillDiff": 100});
+        assert!(deserialize(String::from("custom-elo"), settings).is_ok());
+    }
+
+    #[test]
+    fn test_register_duplicate_rejected() {
+        assert!(register("elo", EloMatchmaker::deserialize).is_err());
     }
 }
@@ -0,0 +1,254 @@
+use crate::entry::Entry;
+use crate::storage::{Storage, StorageError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, RwLock};
+use tracing::info;
+use uuid::Uuid;
+
+/// Attributes of a player kept by the server, merged into the metadata of every entry the
+/// player joins with so clients can't choose them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerProfile {
+    #[serde(default = "Uuid::nil")]
+    pub player: Uuid,
+    /// Merged as `elo`, takes precedence over the rating from reported matches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<f64>,
+    /// Merged as `region`, limits the regional matchmaker to this region
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<String>,
+    /// Any other metadata, merged under its own key
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub attributes: Map<String, Value>,
+    #[serde(default = "Utc::now")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl PlayerProfile {
+    pub fn new(player: Uuid) -> Self {
+        Self {
+            player,
+            rating: None,
+            region: None,
+            roles: Vec::new(),
+            flags: Vec::new(),
+            attributes: Map::new(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    /// The profile as entry metadata.
+    pub fn metadata(&self) -> Map<String, Value> {
+        let mut metadata = self.attributes.clone();
+        if let Some(rating) = self.rating {
+            metadata.insert(String::from("elo"), json!(rating.round() as i64));
+        }
+        if let Some(region) = &self.region {
+            metadata.insert(String::from("region"), json!(region));
+        }
+        if !self.roles.is_empty() {
+            metadata.insert(String::from("roles"), json!(self.roles));
+        }
+        if !self.flags.is_empty() {
+            metadata.insert(String::from("flags"), json!(self.flags));
+        }
+        metadata
+    }
+}
+
+/// Where player profiles are kept.
+pub trait ProfileStore: Send + Sync {
+    /// Profiles of `players`, players without a profile are left out.
+    fn get_profiles(&self, players: &[Uuid]) -> Result<Vec<PlayerProfile>, StorageError>;
+
+    /// Profiles ordered by player id.
+    fn list_profiles(&self, offset: usize, limit: usize) -> Result<Vec<PlayerProfile>, StorageError>;
+
+    /// Creates or replaces the profile of `profile.player`.
+    fn save_profile(&self, profile: &PlayerProfile) -> Result<(), StorageError>;
+
+    /// Whether the player had a profile.
+    fn delete_profile(&self, player: Uuid) -> Result<bool, StorageError>;
+}
+
+/// Keeps profiles until the server stops, e.g. when profiles are pushed by another service.
+#[derive(Default)]
+pub struct MemoryProfileStore {
+    profiles: RwLock<BTreeMap<Uuid, PlayerProfile>>,
+}
+
+impl MemoryProfileStore {
+    fn poisoned<T>(_: T) -> StorageError {
+        StorageError::Database(String::from("Profile store lock poisoned"))
+    }
+}
+
+impl ProfileStore for MemoryProfileStore {
+    fn get_profiles(&self, players: &[Uuid]) -> Result<Vec<PlayerProfile>, StorageError> {
+        let profiles = self.profiles.read().map_err(Self::poisoned)?;
+        Ok(players.iter().filter_map(|player| profiles.get(player).cloned()).collect())
+    }
+
+    fn list_profiles(&self, offset: usize, limit: usize) -> Result<Vec<PlayerProfile>, StorageError> {
+        let profiles = self.profiles.read().map_err(Self::poisoned)?;
+        Ok(profiles.values().skip(offset).take(limit).cloned().collect())
+    }
+
+    fn save_profile(&self, profile: &PlayerProfile) -> Result<(), StorageError> {
+        let mut profiles = self.profiles.write().map_err(Self::poisoned)?;
+        profiles.insert(profile.player, profile.clone());
+        Ok(())
+    }
+
+    fn delete_profile(&self, player: Uuid) -> Result<bool, StorageError> {
+        let mut profiles = self.profiles.write().map_err(Self::poisoned)?;
+        Ok(profiles.remove(&player).is_some())
+    }
+}
+
+/// Picks the profile store from the `PROFILES` environment variable:
+/// - `storage` (default): next to queues and matches in `storage`
+/// - `memory`: in memory, lost on restart
+pub fn from_env(storage: Arc<dyn Storage>) -> Result<Arc<dyn ProfileStore>, StorageError> {
+    let backend = std::env::var("PROFILES").unwrap_or_else(|_| String::from("storage"));
+
+    match backend.as_str() {
+        "storage" => Ok(storage),
+        "memory" => {
+            info!("Keeping player profiles in memory");
+            Ok(Arc::new(MemoryProfileStore::default()))
+        }
+        other => Err(StorageError::Config(format!("Unknown profile store: {}", other))),
+    }
+}
+
+/// Merges the profiles of an entry's players into its metadata. A key is only replaced if one
+/// of the profiles sets it, so clients still choose everything the profiles leave out.
+///
+/// Single players get the profile's values as they are, parties get a list with one value per
+/// player like `elo` and `roles` accept. If only some players of a party have a key, their values
+/// replace theirs in the list the client sent, and the key is removed if the client didn't send
+/// one value per player.
+pub fn merge_profiles(entry: &mut Entry, profiles: &[PlayerProfile]) {
+    let by_player: HashMap<Uuid, Map<String, Value>> =
+        profiles.iter().map(|profile| (profile.player, profile.metadata())).collect();
+    let player_metadata: Vec<Option<&Map<String, Value>>> =
+        entry.players.iter().map(|player| by_player.get(player)).collect();
+
+    let keys: BTreeSet<&String> = player_metadata.iter().flatten().flat_map(|x| x.keys()).collect();
+    for key in keys {
+        let values: Vec<Option<Value>> = player_metadata
+            .iter()
+            .map(|x| x.and_then(|metadata| metadata.get(key)).cloned())
+            .collect();
+
+        let value = match values.as_slice() {
+            [Some(value)] => Some(value.clone()),
+            _ => match entry.metadata.remove(key) {
+                Some(Value::Array(mut sent)) if sent.len() == values.len() => {
+                    for (sent, value) in sent.iter_mut().zip(values) {
+                        if let Some(value) = value {
+                            *sent = value;
+                        }
+                    }
+                    Some(Value::Array(sent))
+                }
+                _ => values.into_iter().collect::<Option<Vec<Value>>>().map(Value::Array),
+            },
+        };
+        if let Some(value) = value {
+            entry.metadata.insert(key.clone(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(rating: f64, roles: &[&str]) -> PlayerProfile {
+        PlayerProfile {
+            rating: Some(rating),
+            roles: roles.iter().map(|x| String::from(*x)).collect(),
+            ..PlayerProfile::new(Uuid::new_v4())
+        }
+    }
+
+    #[test]
+    fn test_merge_single_player() {
+        let mut profile = profile(1500.4, &["tank"]);
+        profile.region = Some(String::from("eu-west"));
+        profile.attributes.insert(String::from("platform"), json!("pc"));
+
+        let mut metadata = Map::new();
+        metadata.insert(String::from("elo"), json!(3000));
+        metadata.insert(String::from("mode"), json!("ranked"));
+        let mut entry = Entry::new(Uuid::new_v4(), vec![profile.player], metadata);
+        merge_profiles(&mut entry, &[profile]);
+
+        assert_eq!(entry.metadata["elo"], json!(1500));
+        assert_eq!(entry.metadata["region"], json!("eu-west"));
+        assert_eq!(entry.metadata["roles"], json!(["tank"]));
+        assert_eq!(entry.metadata["platform"], json!("pc"));
+        assert_eq!(entry.metadata["mode"], json!("ranked"));
+
+        // Without a profile the client's metadata is kept
+        let mut metadata = Map::new();
+        metadata.insert(String::from("roles"), json!(["healer"]));
+        let mut entry = Entry::new(Uuid::new_v4(), vec![Uuid::new_v4()], metadata.clone());
+        merge_profiles(&mut entry, &[]);
+        assert_eq!(entry.metadata, metadata);
+    }
+
+    #[test]
+    fn test_merge_party() {
+        let first = profile(1000.0, &["tank"]);
+        let mut second = profile(1200.0, &[]);
+        second.flags.push(String::from("streamer"));
+
+        let mut entry = Entry::new(Uuid::new_v4(), vec![first.player, second.player], Map::new());
+        merge_profiles(&mut entry, &[first.clone(), second]);
+
+        assert_eq!(entry.metadata["elo"], json!([1000, 1200]));
+        assert!(!entry.metadata.contains_key("roles"));
+        assert!(!entry.metadata.contains_key("flags"));
+
+        // Players without a profile keep what the client sent for them
+        let mut metadata = Map::new();
+        metadata.insert(String::from("elo"), json!([1100, 1300]));
+        metadata.insert(String::from("roles"), json!([["damage"], ["support"]]));
+        metadata.insert(String::from("platform"), json!("pc"));
+        metadata.insert(String::from("mode"), json!("ranked"));
+        let mut entry = Entry::new(Uuid::new_v4(), vec![first.player, Uuid::new_v4()], metadata);
+        let mut first = first;
+        first.attributes.insert(String::from("platform"), json!("console"));
+        merge_profiles(&mut entry, &[first]);
+        assert_eq!(entry.metadata["elo"], json!([1000, 1300]));
+        assert_eq!(entry.metadata["roles"], json!([["tank"], ["support"]]));
+        // Not one value per player, so there's nothing to keep the profile's value apart from
+        assert!(!entry.metadata.contains_key("platform"));
+        assert_eq!(entry.metadata["mode"], json!("ranked"));
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let store = MemoryProfileStore::default();
+        let profile = profile(1000.0, &[]);
+
+        store.save_profile(&profile).unwrap();
+        assert_eq!(store.get_profiles(&[profile.player, Uuid::new_v4()]).unwrap(), vec![profile.clone()]);
+        assert_eq!(store.list_profiles(0, 10).unwrap().len(), 1);
+        assert!(store.list_profiles(1, 10).unwrap().is_empty());
+
+        assert!(store.delete_profile(profile.player).unwrap());
+        assert!(!store.delete_profile(profile.player).unwrap());
+    }
+}
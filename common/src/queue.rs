@@ -1,54 +1,258 @@
 use crate::entry::{Entry, EntryId};
-use crate::matchmaker::{Matchmaker, MatchmakerResult};
+use crate::matchmaker::{Matchmaker, MatchmakerError, MatchmakerResult};
+use crate::schema::{MetadataError, MetadataSchema};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
 use serde_json::Value;
+use thiserror::Error;
+use tokio::sync::Notify;
+use tokio::time::{Duration, Instant};
 use tracing::warn;
 use uuid::Uuid;
 
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum QueueError {
+    #[error(transparent)]
+    InvalidMetadata(#[from] MetadataError),
+    #[error(transparent)]
+    Matchmaker(#[from] MatchmakerError),
+}
+
+impl QueueError {
+    /// Stable, machine-readable code clients can branch on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            QueueError::InvalidMetadata(_) => "INVALID_METADATA",
+            QueueError::Matchmaker(err) => err.code(),
+        }
+    }
+}
+
+/// When a queue's matchmaker runs.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "mode", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum TickMode {
+    /// Every `interval_ms`, and right away when entries join or leave
+    Interval { interval_ms: u64 },
+    /// Once entries join or leave and no further change happened for `debounce_ms`, and at
+    /// least every `max_interval_ms` so time based matchmaker windows still widen
+    Event { debounce_ms: u64, max_interval_ms: u64 },
+}
+
+impl Default for TickMode {
+    fn default() -> Self {
+        TickMode::Interval { interval_ms: 1000 }
+    }
+}
+
+impl TickMode {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            TickMode::Interval { interval_ms } if *interval_ms == 0 => {
+                Err(String::from("intervalMs must be a positive integer"))
+            }
+            TickMode::Event { max_interval_ms, .. } if *max_interval_ms == 0 => {
+                Err(String::from("maxIntervalMs must be a positive integer"))
+            }
+            TickMode::Event {
+                debounce_ms,
+                max_interval_ms,
+            } if debounce_ms > max_interval_ms => {
+                Err(String::from("debounceMs can't be larger than maxIntervalMs"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Waits until the queue should tick next.
+    pub async fn wait(&self, changed: &Notify) {
+        match *self {
+            TickMode::Interval { interval_ms } => {
+                tokio::select! {
+                    _ = changed.notified() => {}
+                    _ = tokio::time::sleep(Duration::from_millis(interval_ms)) => {}
+                }
+            }
+            TickMode::Event {
+                debounce_ms,
+                max_interval_ms,
+            } => {
+                let deadline = Instant::now() + Duration::from_millis(max_interval_ms);
+                tokio::select! {
+                    _ = changed.notified() => {}
+                    _ = tokio::time::sleep_until(deadline) => {}
+                }
+
+                // Keep waiting while changes arrive, but never past the deadline
+                loop {
+                    let until = deadline.min(Instant::now() + Duration::from_millis(debounce_ms));
+                    tokio::select! {
+                        _ = changed.notified() => {}
+                        _ = tokio::time::sleep_until(until) => break,
+                    }
+                }
+            }
+        }
+    }
+}
+
 pub struct Queue {
     pub id: String,
+    /// Name the matchmaker was registered under, which can differ from its type name
+    matchmaker_type: String,
     matchmaker: Box<dyn Matchmaker>,
+    schema: MetadataSchema,
     entries: HashMap<EntryId, Entry>,
+    /// Paused queues form no matches
+    paused: bool,
+    /// Whether a paused queue also refuses new entries
+    reject_joins: bool,
+    tick_mode: TickMode,
+    /// Notified whenever an entry joins or leaves
+    changed: Arc<Notify>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct QueueResult {
+    /// Id of the match in the match history
+    #[serde(rename = "matchId")]
+    pub match_id: Uuid,
     pub teams: Vec<Vec<Entry>>,
     pub game: Value,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub roles: HashMap<Uuid, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
 }
 
 impl QueueResult {
-    pub fn new(teams: Vec<Vec<Entry>>, game: Value) -> Self {
-        Self { teams, game }
+    pub fn new(
+        match_id: Uuid,
+        teams: Vec<Vec<Entry>>,
+        game: Value,
+        roles: HashMap<Uuid, String>,
+        region: Option<String>,
+    ) -> Self {
+        Self {
+            match_id,
+            teams,
+            game,
+            roles,
+            region,
+        }
     }
 }
 
 impl Queue {
     pub fn new(
         id: String,
+        matchmaker_type: String,
         matchmaker: Box<dyn Matchmaker>,
         entries: HashMap<EntryId, Entry>,
-    ) -> Self {
-        Self {
+        schema: Option<Value>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let schema = MetadataSchema::new(matchmaker.metadata_schema(), schema)?;
+
+        Ok(Self {
             id,
+            matchmaker_type,
             matchmaker,
+            schema,
             entries,
-        }
+            paused: false,
+            reject_joins: false,
+            tick_mode: TickMode::default(),
+            changed: Arc::new(Notify::new()),
+        })
+    }
+
+    pub fn tick_mode(&self) -> &TickMode {
+        &self.tick_mode
+    }
+
+    pub fn set_tick_mode(&mut self, tick_mode: TickMode) -> Result<(), String> {
+        tick_mode.validate()?;
+        self.tick_mode = tick_mode;
+        Ok(())
+    }
+
+    /// Notified whenever an entry joins or leaves the queue.
+    pub fn changed(&self) -> Arc<Notify> {
+        self.changed.clone()
     }
 
     pub fn tick(&self) -> MatchmakerResult {
+        if self.paused {
+            return MatchmakerResult::Skip(String::from("Queue is paused"));
+        }
         self.matchmaker.matchmake()
     }
 
-    pub fn add_entry(&mut self, entry: Entry) -> Result<(), Box<dyn std::error::Error>> {
+    /// Stops forming matches until resumed. Waiting entries stay in the queue.
+    pub fn pause(&mut self, reject_joins: bool) {
+        self.paused = true;
+        self.reject_joins = reject_joins;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.reject_joins = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Whether the queue is paused and refuses new entries.
+    pub fn rejects_joins(&self) -> bool {
+        self.paused && self.reject_joins
+    }
+
+    pub fn add_entry(&mut self, entry: Entry) -> Result<(), QueueError> {
+        self.schema.validate(&entry.metadata)?;
         self.matchmaker.add_entry(entry.clone())?;
         self.entries.insert(entry.id, entry);
+        self.changed.notify_one();
         Ok(())
     }
 
-    pub fn matchmaker(&self) -> &Box<dyn Matchmaker> {
-        &self.matchmaker
+    /// Swaps in a new matchmaker, e.g. with changed settings, keeping the waiting entries and
+    /// their time queued. Entries the new matchmaker rejects are removed and returned with the
+    /// reason.
+    pub fn set_matchmaker(
+        &mut self,
+        matchmaker: Box<dyn Matchmaker>,
+    ) -> Result<Vec<(Entry, QueueError)>, Box<dyn std::error::Error>> {
+        let schema = MetadataSchema::new(matchmaker.metadata_schema(), self.schema.schema().cloned())?;
+
+        self.matchmaker = matchmaker;
+        self.schema = schema;
+
+        let mut entries: Vec<Entry> = self.entries.drain().map(|(_, entry)| entry).collect();
+        entries.sort_by_key(|entry| entry.time_queued);
+
+        let mut evicted: Vec<(Entry, QueueError)> = Vec::new();
+        for entry in entries {
+            if let Err(err) = self.add_entry(entry.clone()) {
+                evicted.push((entry, err));
+            }
+        }
+        Ok(evicted)
+    }
+
+    pub fn matchmaker(&self) -> &dyn Matchmaker {
+        self.matchmaker.as_ref()
+    }
+
+    /// Registered name of the queue's matchmaker, used to rebuild it from its settings.
+    pub fn matchmaker_type(&self) -> &str {
+        &self.matchmaker_type
+    }
+
+    /// The metadata schema configured on this queue, if any.
+    pub fn schema(&self) -> Option<&Value> {
+        self.schema.schema()
     }
 
     pub fn entries(&self) -> &HashMap<EntryId, Entry> {
@@ -59,7 +263,20 @@ impl Queue {
         self.entries.values().any(|x| x.players.contains(player_id))
     }
 
+    pub fn remove_all(&mut self) -> Vec<Entry> {
+        self.matchmaker.remove_all();
+        self.entries.drain().map(|(_, entry)| entry).collect()
+    }
+
     pub fn remove_entry(&mut self, entry_id: &EntryId) -> Option<Entry> {
+        let entry = self.take_entry(entry_id);
+        self.changed.notify_one();
+        entry
+    }
+
+    /// Removes an entry the queue's own tick is done with, e.g. because it was matched. Unlike
+    /// `remove_entry` this isn't a change that makes the queue tick again.
+    pub fn take_entry(&mut self, entry_id: &EntryId) -> Option<Entry> {
         let entry = self.entries.remove(entry_id);
         let m_entry = self.matchmaker.remove_entry(entry_id);
 
@@ -70,3 +287,94 @@ impl Queue {
         entry
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_tick_mode_from_json() {
+        let tick_mode: TickMode =
+            serde_json::from_value(json!({"mode": "event", "debounceMs": 200, "maxIntervalMs": 5000}))
+                .unwrap();
+
+        assert_eq!(
+            tick_mode,
+            TickMode::Event {
+                debounce_ms: 200,
+                max_interval_ms: 5000
+            }
+        );
+    }
+
+    #[test]
+    fn test_tick_mode_validated() {
+        assert!(TickMode::Interval { interval_ms: 0 }.validate().is_err());
+        assert!(TickMode::Event { debounce_ms: 500, max_interval_ms: 100 }.validate().is_err());
+        assert!(TickMode::Event { debounce_ms: 100, max_interval_ms: 500 }.validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_event_tick_debounced() {
+        let tick_mode = TickMode::Event {
+            debounce_ms: 20,
+            max_interval_ms: 5000,
+        };
+        let changed = Notify::new();
+        changed.notify_one();
+
+        let start = Instant::now();
+        tick_mode.wait(&changed).await;
+
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(start.elapsed() < Duration::from_millis(5000));
+    }
+
+    #[tokio::test]
+    async fn test_interval_tick_on_change() {
+        let tick_mode = TickMode::Interval { interval_ms: 5000 };
+        let changed = Notify::new();
+        changed.notify_one();
+
+        let start = Instant::now();
+        tick_mode.wait(&changed).await;
+
+        assert!(start.elapsed() < Duration::from_millis(5000));
+    }
+
+    #[tokio::test]
+    async fn test_taken_entries_dont_notify() {
+        let settings = json!({"numberOfTeams": 2, "teamSize": 1, "minEntrySize": 1, "maxEntrySize": 1});
+        let matchmaker = crate::matchmaker::deserialize(String::from("flexible"), settings).unwrap();
+        let mut queue =
+            Queue::new(String::from("test"), String::from("flexible"), matchmaker, HashMap::new(), None).unwrap();
+        let first = Entry::new(Uuid::new_v4(), vec![Uuid::new_v4()], Default::default());
+        let second = Entry::new(Uuid::new_v4(), vec![Uuid::new_v4()], Default::default());
+        queue.add_entry(first.clone()).unwrap();
+        queue.add_entry(second.clone()).unwrap();
+        let changed = queue.changed();
+        changed.notified().await;
+
+        assert!(queue.take_entry(&first.id).is_some());
+        let woken = tokio::time::timeout(Duration::from_millis(20), changed.notified()).await;
+        assert!(woken.is_err());
+
+        assert!(queue.remove_entry(&second.id).is_some());
+        let woken = tokio::time::timeout(Duration::from_millis(20), changed.notified()).await;
+        assert!(woken.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_event_tick_max_interval() {
+        let tick_mode = TickMode::Event {
+            debounce_ms: 10,
+            max_interval_ms: 50,
+        };
+
+        let start = Instant::now();
+        tick_mode.wait(&Notify::new()).await;
+
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
@@ -1,116 +1,323 @@
 use crate::entry::{Entry, EntryId};
 use crate::gamefinder::{GameFinder};
 use crate::matchmaker;
-use crate::matchmaker::MatchmakerResult;
-use crate::queue::{Queue, QueueResult};
+use crate::matchmaker::{MatchedTeams, MatchmakerResult};
+use crate::profile::{PlayerProfile, ProfileStore};
+use crate::queue::{Queue, QueueError, QueueResult, TickMode};
+use crate::rating::PlayerRating;
+use crate::storage::{MatchRecord, QueueDefinition, Storage};
+use crate::{profile, rating, schema};
+use chrono::{DateTime, Utc};
 use serde_json::Value;
-use std::collections::HashMap;
-use std::error::Error;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use thiserror::Error;
 use tokio::sync::oneshot::{Receiver, Sender};
 use tokio::sync::{Mutex, MutexGuard};
+use tokio::task::{JoinHandle, JoinSet};
 use tracing::{info, warn};
 use uuid::Uuid;
 
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum QueueTrackerError {
+    #[error("QueueTracker is locked, no new entries can be added")]
+    Locked,
+    #[error("Queue {0} not found")]
+    QueueNotFound(String),
+    #[error("Queue {0} already exists")]
+    QueueAlreadyExists(String),
+    #[error("Queue {0} was closed")]
+    QueueClosed(String),
+    #[error("Queue {0} is paused")]
+    QueuePaused(String),
+    #[error("Player {0} is already in this queue")]
+    PlayerAlreadyQueued(Uuid),
+    #[error("Invalid queue settings: {0}")]
+    InvalidSettings(String),
+    #[error(transparent)]
+    Queue(#[from] QueueError),
+    /// The queue's settings changed and the entry no longer fits them
+    #[error("Removed from queue after a settings change: {0}")]
+    Evicted(QueueError),
+    /// The matchmaker failed while forming matches, the entry was removed from the queue
+    #[error("Matchmaker failed: {0}")]
+    MatchmakerFailed(String),
+    #[error("Failed to find a game: {0}")]
+    GameNotFound(String),
+    #[error("Storage failed: {0}")]
+    StorageFailed(String),
+}
+
+impl QueueTrackerError {
+    /// Stable, machine-readable code clients can branch on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            QueueTrackerError::Locked => "QUEUE_TRACKER_LOCKED",
+            QueueTrackerError::QueueNotFound(_) => "QUEUE_NOT_FOUND",
+            QueueTrackerError::QueueAlreadyExists(_) => "QUEUE_ALREADY_EXISTS",
+            QueueTrackerError::QueueClosed(_) => "QUEUE_CLOSED",
+            QueueTrackerError::QueuePaused(_) => "QUEUE_PAUSED",
+            QueueTrackerError::PlayerAlreadyQueued(_) => "PLAYER_ALREADY_QUEUED",
+            QueueTrackerError::InvalidSettings(_) => "INVALID_SETTINGS",
+            QueueTrackerError::Queue(err) => err.code(),
+            QueueTrackerError::Evicted(_) => "ENTRY_EVICTED",
+            QueueTrackerError::MatchmakerFailed(_) => "MATCHMAKER_ERROR",
+            QueueTrackerError::GameNotFound(_) => "GAME_NOT_FOUND",
+            QueueTrackerError::StorageFailed(_) => "STORAGE_ERROR",
+        }
+    }
+}
+
+/// How long entries restored after a restart keep their place in line, waiting for their
+/// players to reconnect, unless set with `RESTORE_WINDOW_SECONDS`.
+const DEFAULT_RESTORE_WINDOW_SECONDS: i64 = 300;
+
+fn restore_window() -> chrono::Duration {
+    let seconds = std::env::var("RESTORE_WINDOW_SECONDS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(DEFAULT_RESTORE_WINDOW_SECONDS);
+    chrono::Duration::seconds(seconds)
+}
+
+/// An entry that was waiting when the server stopped. It isn't matched until its players
+/// reconnect with the same entry id, so nobody is put in a game they aren't around for.
+struct RestoredEntry {
+    queue: String,
+    entry: Entry,
+    expires: DateTime<Utc>,
+}
+
+/// Sent to a waiting entry once it was matched, or removed from the queue because of an error.
+pub type QueueSender = Sender<Result<QueueResult, QueueTrackerError>>;
+
+/// What the server knows about the players of a joining entry, looked up with
+/// [`QueueTracker::lookup`] before the tracker is locked for [`QueueTracker::join`].
+#[derive(Default, Debug, Clone)]
+pub struct PlayerData {
+    /// Stored ratings in the queue's current season, empty if its matchmaker doesn't use them
+    pub ratings: Vec<PlayerRating>,
+    /// Profiles of the players that have one
+    pub profiles: Vec<PlayerProfile>,
+}
+
 pub struct QueueTracker {
     pub queues: HashMap<String, Arc<Mutex<Queue>>>,
-    pub senders: HashMap<EntryId, Sender<Result<QueueResult, String>>>,
+    pub senders: HashMap<EntryId, QueueSender>,
     pub game_finder: GameFinder,
+    /// Where queues are kept across restarts
+    storage: Arc<dyn Storage>,
+    /// Profiles merged into the metadata of joining entries
+    profiles: Arc<dyn ProfileStore>,
     pub locked: bool,
+    /// Tick task of every queue, each stops on its own once its queue is deleted
+    tasks: HashMap<String, JoinHandle<()>>,
+    /// Queues being deleted once their waiting entries are matched
+    draining: HashSet<String>,
+    restored: HashMap<EntryId, RestoredEntry>,
 }
 
 impl QueueTracker {
-    pub fn new(game_finder: GameFinder) -> Self {
+    pub fn new(game_finder: GameFinder, storage: Arc<dyn Storage>, profiles: Arc<dyn ProfileStore>) -> Self {
         Self {
             queues: HashMap::new(),
             senders: HashMap::new(),
             game_finder,
+            storage,
+            profiles,
             locked: false,
+            tasks: HashMap::new(),
+            draining: HashSet::new(),
+            restored: HashMap::new(),
         }
     }
 
-    pub async fn from_file(game_finder: GameFinder) -> Arc<Mutex<Self>> {
-        let tracker = Arc::new(Mutex::new(Self::new(game_finder)));
+    pub fn storage(&self) -> Arc<dyn Storage> {
+        self.storage.clone()
+    }
 
-        let data = tokio::fs::read_to_string("queues.json").await;
-        let Ok(data) = data else {
-            info!("No queues.json file found, starting with empty QueueTracker");
-            tokio::fs::write("queues.json", "[]").await.ok();
-            return tracker;
-        };
-        let Ok(queues_json) = serde_json::from_str::<Vec<Value>>(&data) else {
-            warn!("Failed to parse queues.json, starting with empty QueueTracker");
-            return tracker;
-        };
+    /// Creates a tracker with every queue kept in `storage`.
+    pub async fn load(
+        game_finder: GameFinder,
+        storage: Arc<dyn Storage>,
+        profiles: Arc<dyn ProfileStore>,
+    ) -> Arc<Mutex<Self>> {
+        let tracker = Arc::new(Mutex::new(Self::new(game_finder, storage.clone(), profiles)));
 
-        for value in queues_json {
-            let Some(name) = value
-                .get("name")
-                .and_then(|v| v.as_str())
-                .map(|x| String::from(x))
-            else {
-                warn!("Queue in queues.json has no name, skipping");
-                continue;
-            };
-            let Some(matchmaker_id) = value
-                .get("matchmaker")
-                .and_then(|v| v.as_str())
-                .map(|x| String::from(x))
-            else {
-                warn!("Queue {} in queues.json has no matchmaker, skipping", name);
-                continue;
-            };
-            let Some(settings) = value.get("settings") else {
-                warn!("Queue {} in queues.json has no settings, skipping", name);
-                continue;
-            };
+        let queues = match tokio::task::spawn_blocking(move || storage.load_queues()).await {
+            Ok(Ok(queues)) => queues,
+            Ok(Err(err)) => {
+                warn!("Failed to load queues, starting with empty QueueTracker: {}", err);
+                return tracker;
+            }
+            Err(err) => {
+                warn!("Failed to load queues, starting with empty QueueTracker: {}", err);
+                return tracker;
+            }
+        };
 
+        for queue in queues {
+            let name = queue.name;
             if Self::create(
                 tracker.clone(),
                 name.clone(),
-                matchmaker_id,
-                settings.clone(),
+                queue.matchmaker,
+                queue.settings,
+                queue.schema,
+                queue.tick,
                 false,
             )
             .await
             .is_ok()
             {
-                info!("Loaded queue {} from file", name);
+                let mut tracker = tracker.lock().await;
+                if queue.paused {
+                    tracker.pause(&name, queue.reject_joins, false).await.ok();
+                }
+                tracker.restore(&name, queue.entries);
+                info!("Loaded queue {} from storage", name);
             } else {
-                warn!("Failed to create queue {} from file", name);
+                warn!("Failed to create queue {} from storage", name);
             }
         }
         tracker
     }
 
-    pub async fn save_to_file(&self) {
-        let mut queues: Vec<Value> = Vec::new();
+    pub async fn save(&self) {
+        let mut queues: Vec<QueueDefinition> = Vec::new();
 
         for (name, queue_mutex) in &self.queues {
+            // Draining queues are already deleted as far as restarts are concerned
+            if self.draining.contains(name) {
+                continue;
+            }
             let queue = queue_mutex.lock().await;
             let matchmaker = queue.matchmaker();
-            let matchmaker_type = matchmaker.get_type_name();
             let Ok(settings) = matchmaker.serialize() else {
                 warn!("Failed to serialize matchmaker for queue {}", name);
                 continue;
             };
 
-            let queue_json = serde_json::json!({
-                "name": name,
-                "matchmaker": matchmaker_type,
-                "settings": settings,
+            // Restored entries whose players haven't reconnected yet keep their place too
+            let now = Utc::now();
+            let entries: Vec<Entry> = queue
+                .entries()
+                .values()
+                .chain(
+                    self.restored
+                        .values()
+                        .filter(|x| x.queue == *name && x.expires > now)
+                        .map(|x| &x.entry),
+                )
+                .cloned()
+                .collect();
+
+            queues.push(QueueDefinition {
+                name: name.clone(),
+                matchmaker: queue.matchmaker_type().to_string(),
+                settings,
+                schema: queue.schema().cloned(),
+                tick: queue.tick_mode().clone(),
+                paused: queue.is_paused(),
+                reject_joins: queue.is_paused() && queue.rejects_joins(),
+                entries,
             });
-            queues.push(queue_json);
         }
 
-        if let Err(e) = std::fs::write(
-            "queues.json",
-            serde_json::to_string_pretty(&queues).unwrap(),
-        ) {
-            warn!("Failed to write queues to file: {}", e);
+        let storage = self.storage.clone();
+        match tokio::task::spawn_blocking(move || storage.save_queues(&queues)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => warn!("Failed to save queues: {}", err),
+            Err(err) => warn!("Failed to save queues: {}", err),
+        }
+    }
+
+    /// Holds entries that were waiting in a queue before a restart until their players
+    /// reconnect.
+    fn restore(&mut self, queue_id: &str, entries: Vec<Entry>) {
+        let expires = Utc::now() + restore_window();
+
+        for entry in entries {
+            self.restored.insert(
+                entry.id,
+                RestoredEntry {
+                    queue: String::from(queue_id),
+                    entry,
+                    expires,
+                },
+            );
+        }
+    }
+
+    /// Gives a reconnecting entry back its original time queued, if it was waiting in the same
+    /// queue with the same players before a restart.
+    fn reclaim_place(&mut self, queue_id: &str, mut entry: Entry) -> Entry {
+        let now = Utc::now();
+        self.restored.retain(|_, x| x.expires > now);
+
+        if let Some(restored) = self.restored.remove(&entry.id)
+            && restored.queue == queue_id
+            && restored.entry.players == entry.players
+        {
+            info!("Entry {:?} resumed its place in queue {}", entry.id, queue_id);
+            entry.time_queued = restored.entry.time_queued;
         }
+        entry
     }
 
+    /// Looks up what [`join`](Self::join) merges into an entry of `players`. The tracker is only
+    /// held to find the queue, so other queues don't wait for the storage.
+    pub async fn lookup(
+        tracker: &Arc<Mutex<Self>>,
+        queue_id: &str,
+        players: &[Uuid],
+    ) -> Result<PlayerData, QueueTrackerError> {
+        let (storage, profiles, queue) = {
+            let tracker = tracker.lock().await;
+            (tracker.storage.clone(), tracker.profiles.clone(), tracker.get_queue(queue_id).await)
+        };
+        // Joining reports the missing queue
+        let Some(queue) = queue else {
+            return Ok(PlayerData::default());
+        };
+        let required = schema::required_keys(&queue.lock().await.matchmaker().metadata_schema());
+
+        let mut data = PlayerData::default();
+        if rating::uses_ratings(&required) {
+            data.ratings = Self::get_ratings(storage, queue_id, players.to_vec()).await?;
+        }
+        data.profiles = Self::get_profiles(profiles, players.to_vec()).await?;
+        Ok(data)
+    }
+
+    async fn get_ratings(
+        storage: Arc<dyn Storage>,
+        queue_id: &str,
+        players: Vec<Uuid>,
+    ) -> Result<Vec<PlayerRating>, QueueTrackerError> {
+        let queue_id = String::from(queue_id);
+
+        tokio::task::spawn_blocking(move || {
+            let season = storage.current_season(&queue_id)?.number;
+            storage.get_ratings(&queue_id, season, &players)
+        })
+            .await
+            .map_err(|err| QueueTrackerError::StorageFailed(err.to_string()))?
+            .map_err(|err| QueueTrackerError::StorageFailed(err.to_string()))
+    }
+
+    async fn get_profiles(
+        profiles: Arc<dyn ProfileStore>,
+        players: Vec<Uuid>,
+    ) -> Result<Vec<PlayerProfile>, QueueTrackerError> {
+        tokio::task::spawn_blocking(move || profiles.get_profiles(&players))
+            .await
+            .map_err(|err| QueueTrackerError::StorageFailed(err.to_string()))?
+            .map_err(|err| QueueTrackerError::StorageFailed(err.to_string()))
+    }
+
+    /// Stops accepting entries and forming matches, so the waiting entries can be saved on
+    /// shutdown without any of them being matched afterwards.
     pub async fn lock(&mut self) {
         self.locked = true;
     }
@@ -120,18 +327,25 @@ impl QueueTracker {
         name: String,
         matchmaker_id: String,
         settings: Value,
+        schema: Option<Value>,
+        tick_mode: TickMode,
         save: bool,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), QueueTrackerError> {
         let tracker_copy = tracker.clone();
         let mut tracker_guard = tracker_copy.lock().await;
 
         if tracker_guard.get_queue(&name).await.is_some() {
-            return Err(String::from("Queue already exists").into());
+            return Err(QueueTrackerError::QueueAlreadyExists(name));
         }
 
-        let matchmaker = matchmaker::deserialize(matchmaker_id, settings)?;
+        let matchmaker = matchmaker::deserialize(matchmaker_id.clone(), settings)
+            .map_err(|err| QueueTrackerError::InvalidSettings(err.to_string()))?;
 
-        let queue = Queue::new(name, matchmaker, HashMap::new());
+        let mut queue = Queue::new(name, matchmaker_id, matchmaker, HashMap::new(), schema)
+            .map_err(|err| QueueTrackerError::InvalidSettings(err.to_string()))?;
+        queue
+            .set_tick_mode(tick_mode)
+            .map_err(QueueTrackerError::InvalidSettings)?;
         let queue_id = &queue.id.clone();
         let queue_ref = Arc::new(Mutex::new(queue));
 
@@ -139,39 +353,59 @@ impl QueueTracker {
             .queues
             .insert(queue_id.clone(), queue_ref.clone());
 
-        Self::start_task(tracker, queue_id);
+        let task = Self::start_task(tracker, queue_id, queue_ref);
+        tracker_guard.tasks.insert(queue_id.clone(), task);
 
         if save {
-            tracker_guard.save_to_file().await;
+            tracker_guard.save().await;
         }
 
         Ok(())
     }
 
+    /// Adds an entry to a queue. `players` are its players' ratings and profiles from
+    /// [`lookup`](Self::lookup), they replace what the client sent.
     pub async fn join(
         &mut self,
         queue_id: &str,
         entry: Entry,
-    ) -> Result<Receiver<Result<QueueResult, String>>, Box<dyn Error>> {
-        let (channel_tx, channel_rx): (
-            Sender<Result<QueueResult, String>>,
-            Receiver<Result<QueueResult, String>>,
-        ) = tokio::sync::oneshot::channel::<Result<QueueResult, String>>();
+        players: PlayerData,
+    ) -> Result<Receiver<Result<QueueResult, QueueTrackerError>>, QueueTrackerError> {
+        let (channel_tx, channel_rx) = tokio::sync::oneshot::channel();
 
         if self.locked {
-            return Err("QueueTracker is locked, no new entries can be added".into());
+            return Err(QueueTrackerError::Locked);
+        }
+
+        if self.draining.contains(queue_id) {
+            return Err(QueueTrackerError::QueueClosed(String::from(queue_id)));
         }
 
-        let queue = self.queues.get_mut(queue_id).ok_or("Queue not found")?;
+        let queue = self
+            .queues
+            .get(queue_id)
+            .cloned()
+            .ok_or_else(|| QueueTrackerError::QueueNotFound(String::from(queue_id)))?;
 
         let mut queue = queue.lock().await;
 
+        if queue.rejects_joins() {
+            return Err(QueueTrackerError::QueuePaused(String::from(queue_id)));
+        }
+
         for entry_player in &entry.players {
             if queue.has_player(entry_player) {
-                return Err(format!("Player {} is already in this queue", entry_player).into());
+                return Err(QueueTrackerError::PlayerAlreadyQueued(*entry_player));
             }
         }
 
+        let mut entry = self.reclaim_place(queue_id, entry);
+        let required = schema::required_keys(&queue.matchmaker().metadata_schema());
+        if rating::uses_ratings(&required) {
+            rating::fill_metadata(&mut entry, queue_id, &required, players.ratings);
+        }
+        // A rating set in the profile takes precedence over the one from reported matches
+        profile::merge_profiles(&mut entry, &players.profiles);
         queue.add_entry(entry.clone())?;
         self.senders.insert(entry.id, channel_tx);
 
@@ -189,12 +423,167 @@ impl QueueTracker {
 
     }
 
+    /// Stops a queue from forming matches, e.g. during game server maintenance. Waiting entries
+    /// stay queued, new entries are refused if `reject_joins` is set.
+    pub async fn pause(
+        &mut self,
+        queue_id: &str,
+        reject_joins: bool,
+        save: bool,
+    ) -> Result<(), QueueTrackerError> {
+        let queue = self
+            .queues
+            .get(queue_id)
+            .ok_or_else(|| QueueTrackerError::QueueNotFound(String::from(queue_id)))?;
+        queue.lock().await.pause(reject_joins);
+
+        if save {
+            self.save().await;
+        }
+        info!("Paused queue {}", queue_id);
+        Ok(())
+    }
+
+    pub async fn resume(&mut self, queue_id: &str, save: bool) -> Result<(), QueueTrackerError> {
+        let queue = self
+            .queues
+            .get(queue_id)
+            .ok_or_else(|| QueueTrackerError::QueueNotFound(String::from(queue_id)))?;
+        queue.lock().await.resume();
+
+        if save {
+            self.save().await;
+        }
+        info!("Resumed queue {}", queue_id);
+        Ok(())
+    }
+
+    /// Changes the settings of a queue's matchmaker and its tick mode, keeping its waiting
+    /// entries. Entries that don't fit the new settings are removed, told why, and returned.
+    /// Nothing changes if either is invalid.
+    pub async fn update(
+        &mut self,
+        queue_id: &str,
+        settings: Option<Value>,
+        tick_mode: Option<TickMode>,
+        save: bool,
+    ) -> Result<Vec<(Entry, QueueError)>, QueueTrackerError> {
+        let queue = self
+            .queues
+            .get(queue_id)
+            .cloned()
+            .ok_or_else(|| QueueTrackerError::QueueNotFound(String::from(queue_id)))?;
+        let mut queue = queue.lock().await;
+
+        if let Some(tick_mode) = &tick_mode {
+            tick_mode.validate().map_err(QueueTrackerError::InvalidSettings)?;
+        }
+
+        let mut evicted = Vec::new();
+        if let Some(settings) = settings {
+            let matchmaker = matchmaker::deserialize(queue.matchmaker_type().to_string(), settings)
+                .map_err(|err| QueueTrackerError::InvalidSettings(err.to_string()))?;
+            evicted = queue
+                .set_matchmaker(matchmaker)
+                .map_err(|err| QueueTrackerError::InvalidSettings(err.to_string()))?;
+        }
+        if let Some(tick_mode) = tick_mode {
+            queue.set_tick_mode(tick_mode).map_err(QueueTrackerError::InvalidSettings)?;
+        }
+        drop(queue);
+
+        for (entry, err) in &evicted {
+            if let Some(sender) = self.senders.remove(&entry.id) {
+                let _ = sender.send(Err(QueueTrackerError::Evicted(err.clone())));
+            }
+        }
+
+        if save {
+            self.save().await;
+        }
+        info!("Updated queue {}, evicted {} entries", queue_id, evicted.len());
+        Ok(evicted)
+    }
+
+    /// Deletes a queue. Waiting entries are told the queue was closed, unless `drain` is set,
+    /// in which case the queue stops accepting entries and is deleted once every waiting entry
+    /// has been matched.
+    pub async fn delete(
+        &mut self,
+        queue_id: &str,
+        drain: bool,
+        save: bool,
+    ) -> Result<(), QueueTrackerError> {
+        let queue = self
+            .queues
+            .get(queue_id)
+            .cloned()
+            .ok_or_else(|| QueueTrackerError::QueueNotFound(String::from(queue_id)))?;
+
+        if drain && !queue.lock().await.entries().is_empty() {
+            self.draining.insert(String::from(queue_id));
+            if save {
+                self.save().await;
+            }
+            return Ok(());
+        }
+
+        // The tick task isn't aborted, so games it's already looking up still reach their
+        // players. It stops before its next tick once the queue is gone.
+        self.tasks.remove(queue_id);
+        self.queues.remove(queue_id);
+        self.draining.remove(queue_id);
+
+        let mut queue = queue.lock().await;
+        queue.changed().notify_one();
+        for entry in queue.remove_all() {
+            if let Some(sender) = self.senders.remove(&entry.id) {
+                let _ = sender.send(Err(QueueTrackerError::QueueClosed(String::from(queue_id))));
+            }
+        }
+        drop(queue);
+
+        if save {
+            self.save().await;
+        }
+        info!("Deleted queue {}", queue_id);
+        Ok(())
+    }
+
+    /// Deletes a draining queue once it is empty. Returns whether the queue is gone.
+    async fn finish_drain(tracker: &Arc<Mutex<Self>>, queue_id: &str) -> bool {
+        let mut tracker = tracker.lock().await;
+        Self::finish_drain_locked(&mut tracker, queue_id).await
+    }
+
+    async fn finish_drain_locked(tracker: &mut Self, queue_id: &str) -> bool {
+        let Some(queue) = tracker.queues.get(queue_id).cloned() else {
+            return true;
+        };
+        if !tracker.draining.contains(queue_id) || !queue.lock().await.entries().is_empty() {
+            return false;
+        }
+
+        tracker.queues.remove(queue_id);
+        tracker.draining.remove(queue_id);
+        tracker.tasks.remove(queue_id);
+        info!("Queue {} drained and deleted", queue_id);
+        true
+    }
+
+    /// Whether `queue` is still the queue tracked as `queue_id`, and not deleted or replaced by
+    /// a new queue with the same name.
+    async fn is_tracked(tracker: &Arc<Mutex<Self>>, queue_id: &str, queue: &Arc<Mutex<Queue>>) -> bool {
+        let tracker = tracker.lock().await;
+        tracker.queues.get(queue_id).is_some_and(|x| Arc::ptr_eq(x, queue))
+    }
+
     pub fn get_queues(&self) -> &HashMap<String, Arc<Mutex<Queue>>> {
         &self.queues
     }
 
     pub async fn get_queue(&self, name: &str) -> Option<Arc<Mutex<Queue>>> {
-        self.queues.get(name).map(|x| x.clone())
+        self.queues.get(name).cloned()
     }
 
     pub async fn all_queues_empty(&self) -> bool {
@@ -207,23 +596,42 @@ impl QueueTracker {
         true
     }
 
-    fn start_task(tracker: Arc<Mutex<Self>>, queue_id: &str) {
+    fn start_task(
+        tracker: Arc<Mutex<Self>>,
+        queue_id: &str,
+        queue: Arc<Mutex<Queue>>,
+    ) -> JoinHandle<()> {
         // Start a background task to process queues
 
         let queue_id = String::from(queue_id);
         tokio::spawn(async move {
 
             loop {
-                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+                // Read every time, so changes to the tick mode apply from the next tick
+                let (tick_mode, changed) = {
+                    let queue = queue.lock().await;
+                    (queue.tick_mode().clone(), queue.changed())
+                };
+                tick_mode.wait(&changed).await;
+                if !Self::is_tracked(&tracker, &queue_id, &queue).await {
+                    break;
+                }
                 Self::tick_task(tracker.clone(), &queue_id).await;
+
+                if Self::finish_drain(&tracker, &queue_id).await {
+                    break;
+                }
             }
-        });
+        })
     }
 
     pub async fn tick_task(tracker: Arc<Mutex<Self>>, queue_id: &str) {
 
         let mut tracker = tracker.lock().await;
-        let queue = tracker.get_queue(&queue_id).await;
+        if tracker.locked {
+            return;
+        }
+        let queue = tracker.get_queue(queue_id).await;
         let Some(queue) = queue else {
             return;
         };
@@ -232,38 +640,87 @@ impl QueueTracker {
         let result = queue.tick();
 
         match result {
-            MatchmakerResult::Matched(teams) => {
-                let senders = teams
-                    .iter()
-                    .flatten()
-                    .filter_map(|id| tracker.senders.remove(id))
-                    .collect::<Vec<Sender<Result<QueueResult, String>>>>();
-
-                let teams_entries: Vec<Vec<Entry>> = teams
-                    .into_iter()
-                    .map(|team| {
-                        team.iter()
-                            .filter_map(|id| queue.remove_entry(id))
-                            .collect()
-                    })
-                    .collect();
+            MatchmakerResult::Matched(matches) => {
+                let mut games = JoinSet::new();
+
+                for MatchedTeams {
+                    teams,
+                    roles,
+                    region,
+                } in matches
+                {
+                    let senders = teams
+                        .iter()
+                        .flatten()
+                        .filter_map(|id| tracker.senders.remove(id))
+                        .collect::<Vec<QueueSender>>();
+
+                    let teams_entries: Vec<Vec<Entry>> = teams
+                        .into_iter()
+                        .map(|team| {
+                            team.iter()
+                                .filter_map(|id| queue.take_entry(id))
+                                .collect()
+                        })
+                        .collect();
+
+                    let game_finder = tracker.game_finder.clone();
+                    let storage = tracker.storage.clone();
+                    let queue_id = queue.id.clone();
+
+                    games.spawn(async move {
+                        let players: &Vec<Vec<Uuid>> = &teams_entries
+                            .iter()
+                            .flatten()
+                            .map(|x| x.players.clone())
+                            .collect::<Vec<Vec<Uuid>>>();
 
-                let players: &Vec<Vec<Uuid>> = &teams_entries.iter().flatten().map(|x| x.players.clone()).collect::<Vec<Vec<Uuid>>>();
+                        let game = game_finder
+                            .find_game(&queue_id, players, region.as_deref())
+                            .await;
 
+                        // Every match is kept, including those no game was found for
+                        let record = MatchRecord::new(
+                            &queue_id,
+                            &teams_entries,
+                            game.as_ref().ok().cloned().unwrap_or(Value::Null),
+                            region.clone(),
+                        );
+                        let match_id = record.id;
 
-                match tracker.game_finder.find_game(&queue.id, players).await {
-                    Ok(game) => {
-                        for sender in senders {
-                            let _ = sender.send(Ok(QueueResult::new(teams_entries.clone(), game.clone())));
+                        // Players get their game first, slow storage only delays the record
+                        match game {
+                            Ok(game) => {
+                                for sender in senders {
+                                    let _ = sender.send(Ok(QueueResult::new(
+                                        match_id,
+                                        teams_entries.clone(),
+                                        game.clone(),
+                                        roles.clone(),
+                                        region.clone(),
+                                    )));
+                                }
+                            }
+                            Err(err) => {
+                                for sender in senders {
+                                    let _ = sender
+                                        .send(Err(QueueTrackerError::GameNotFound(err.to_string())));
+                                }
+                            }
                         }
-                    }
-                    Err(err) => {
-                        for sender in senders {
-                            let _ = sender.send(Err(err.to_string()));
+
+                        match tokio::task::spawn_blocking(move || storage.save_match(&record)).await {
+                            Ok(Ok(())) => {}
+                            Ok(Err(err)) => warn!("Failed to save match {}: {}", match_id, err),
+                            Err(err) => warn!("Failed to save match {}: {}", match_id, err),
                         }
-                    }
+                    });
                 }
 
+                // Entries are already removed, other ticks and joins don't have to wait for games
+                drop(queue);
+                drop(tracker);
+                games.join_all().await;
             }
             MatchmakerResult::Error(err, affected) => {
                 let players: Vec<EntryId> = if let Some(affected) = affected {
@@ -272,20 +729,20 @@ impl QueueTracker {
                     queue
                         .entries()
                         .keys()
-                        .map(|x| x.clone())
+                        .copied()
                         .collect::<Vec<EntryId>>()
                 };
 
                 players.iter().for_each(|x| {
-                    queue.remove_entry(x);
+                    queue.take_entry(x);
                 });
 
-                let senders: Vec<Sender<Result<QueueResult, String>>> = players
+                let senders: Vec<QueueSender> = players
                     .iter()
                     .filter_map(|x| tracker.senders.remove(x))
                     .collect();
                 for sender in senders {
-                    let _ = sender.send(Err(err.clone()));
+                    let _ = sender.send(Err(QueueTrackerError::MatchmakerFailed(err.clone())));
                 }
 
             }
@@ -293,3 +750,429 @@ impl QueueTracker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::MemoryProfileStore;
+    use crate::storage::MatchFilter;
+    use crate::storage::json::JsonStorage;
+    use serde_json::{Map, json};
+
+    fn storage() -> Arc<dyn Storage> {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory).unwrap();
+        Arc::new(JsonStorage::new(directory))
+    }
+
+    fn profiles() -> Arc<dyn ProfileStore> {
+        Arc::new(MemoryProfileStore::default())
+    }
+
+    async fn tracker() -> Arc<Mutex<QueueTracker>> {
+        let tracker = Arc::new(Mutex::new(QueueTracker::new(GameFinder::default(), storage(), profiles())));
+        let settings = json!({"numberOfTeams": 2, "teamSize": 5, "minEntrySize": 1, "maxEntrySize": 2});
+        create(tracker.clone(), "test", settings).await.unwrap();
+        tracker
+    }
+
+    async fn create(
+        tracker: Arc<Mutex<QueueTracker>>,
+        name: &str,
+        settings: Value,
+    ) -> Result<(), QueueTrackerError> {
+        let matchmaker = String::from("flexible");
+        let tick_mode = TickMode::default();
+        QueueTracker::create(tracker, String::from(name), matchmaker, settings, None, tick_mode, false)
+            .await
+    }
+
+    fn entry(players: Vec<Uuid>) -> Entry {
+        Entry::new(Uuid::new_v4(), players, Map::new())
+    }
+
+    #[tokio::test]
+    async fn test_error_codes() {
+        let tracker = tracker().await;
+        let mut tracker = tracker.lock().await;
+        let player = Uuid::new_v4();
+
+        let err = tracker.join("missing", entry(vec![player]), PlayerData::default()).await.unwrap_err();
+        assert_eq!(err.code(), "QUEUE_NOT_FOUND");
+
+        tracker.join("test", entry(vec![player]), PlayerData::default()).await.unwrap();
+        let err = tracker.join("test", entry(vec![player]), PlayerData::default()).await.unwrap_err();
+        assert_eq!(err.code(), "PLAYER_ALREADY_QUEUED");
+
+        let party = (0..3).map(|_| Uuid::new_v4()).collect();
+        let err = tracker.join("test", entry(party), PlayerData::default()).await.unwrap_err();
+        assert_eq!(err.code(), "WRONG_TEAM_SIZE");
+
+        tracker.lock().await;
+        let err = tracker.join("test", entry(vec![Uuid::new_v4()]), PlayerData::default()).await.unwrap_err();
+        assert_eq!(err, QueueTrackerError::Locked);
+    }
+
+    #[tokio::test]
+    async fn test_create_errors() {
+        let tracker = tracker().await;
+
+        let err = create(tracker.clone(), "test", json!({})).await.unwrap_err();
+        assert_eq!(err.code(), "QUEUE_ALREADY_EXISTS");
+
+        let err = create(tracker, "other", json!({})).await.unwrap_err();
+        assert_eq!(err.code(), "INVALID_SETTINGS");
+    }
+
+    #[tokio::test]
+    async fn test_registered_name_saved() {
+        crate::matchmaker::register("saved-elo", crate::algo::elo::EloMatchmaker::deserialize).unwrap();
+        let storage = storage();
+        let tracker = Arc::new(Mutex::new(QueueTracker::new(GameFinder::default(), storage.clone(), profiles())));
+        let settings = json!({"scalingFactor": 1.0, "teamSize": 1, "maxSkillDiff": 100});
+        let matchmaker = String::from("saved-elo");
+        let tick_mode = TickMode::default();
+        QueueTracker::create(tracker.clone(), String::from("test"), matchmaker, settings, None, tick_mode, true)
+            .await
+            .unwrap();
+
+        let queues = storage.load_queues().unwrap();
+        assert_eq!(queues[0].matchmaker, "saved-elo");
+    }
+
+    #[tokio::test]
+    async fn test_restored_entry_keeps_place() {
+        let tracker = tracker().await;
+        let mut tracker = tracker.lock().await;
+
+        let mut waiting = entry(vec![Uuid::new_v4()]);
+        waiting.time_queued -= chrono::Duration::seconds(120);
+        let saved: Entry = serde_json::from_value(serde_json::to_value(&waiting).unwrap()).unwrap();
+        assert_eq!(saved.time_queued, waiting.time_queued);
+
+        let mut other = entry(vec![Uuid::new_v4()]);
+        other.time_queued -= chrono::Duration::seconds(120);
+        tracker.restore("test", vec![saved, other.clone()]);
+
+        // Restored entries aren't matched until their players reconnect
+        let queue = tracker.get_queue("test").await.unwrap();
+        assert!(queue.lock().await.entries().is_empty());
+
+        let reconnected = Entry::new(waiting.id.0, waiting.players.clone(), Map::new());
+        tracker.join("test", reconnected, PlayerData::default()).await.unwrap();
+        // Different players, so not the same entry
+        let impostor = Entry::new(other.id.0, vec![Uuid::new_v4()], Map::new());
+        tracker.join("test", impostor, PlayerData::default()).await.unwrap();
+
+        let queue = queue.lock().await;
+        assert_eq!(queue.entries()[&waiting.id].time_queued, waiting.time_queued);
+        assert!(queue.entries()[&other.id].time_queued > other.time_queued);
+    }
+
+    #[tokio::test]
+    async fn test_paused_queue_forms_no_matches() {
+        let tracker = Arc::new(Mutex::new(QueueTracker::new(GameFinder::default(), storage(), profiles())));
+        let settings = json!({"numberOfTeams": 2, "teamSize": 1, "minEntrySize": 1, "maxEntrySize": 1});
+        create(tracker.clone(), "test", settings).await.unwrap();
+        let mut tracker = tracker.lock().await;
+
+        tracker.pause("test", false, false).await.unwrap();
+        tracker.join("test", entry(vec![Uuid::new_v4()]), PlayerData::default()).await.unwrap();
+        tracker.join("test", entry(vec![Uuid::new_v4()]), PlayerData::default()).await.unwrap();
+
+        let queue = tracker.get_queue("test").await.unwrap();
+        assert!(queue.lock().await.tick().is_skip());
+
+        tracker.resume("test", false).await.unwrap();
+        assert!(queue.lock().await.tick().is_matched());
+    }
+
+    #[tokio::test]
+    async fn test_locked_tracker_forms_no_matches() {
+        let tracker = Arc::new(Mutex::new(QueueTracker::new(GameFinder::default(), storage(), profiles())));
+        let settings = json!({"numberOfTeams": 2, "teamSize": 1, "minEntrySize": 1, "maxEntrySize": 1});
+        create(tracker.clone(), "test", settings).await.unwrap();
+
+        // Holding the tracker keeps the tick task from matching them before the lock
+        let mut guard = tracker.lock().await;
+        guard.join("test", entry(vec![Uuid::new_v4()]), PlayerData::default()).await.unwrap();
+        guard.join("test", entry(vec![Uuid::new_v4()]), PlayerData::default()).await.unwrap();
+        guard.lock().await;
+        drop(guard);
+
+        QueueTracker::tick_task(tracker.clone(), "test").await;
+
+        let queue = tracker.lock().await.get_queue("test").await.unwrap();
+        assert_eq!(queue.lock().await.entries().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_matches_recorded() {
+        // Nothing listens on port 1, so no game is found
+        let mut game_finder = GameFinder::default();
+        game_finder.config.base_url = String::from("http://127.0.0.1:1/{playlist}");
+        let storage = storage();
+        let tracker = Arc::new(Mutex::new(QueueTracker::new(game_finder, storage.clone(), profiles())));
+        let settings = json!({"numberOfTeams": 2, "teamSize": 1, "minEntrySize": 1, "maxEntrySize": 1});
+        // Only the tick below forms the match, so the record is saved once it returns
+        let tick_mode = TickMode::Event {
+            debounce_ms: 60_000,
+            max_interval_ms: 60_000,
+        };
+        let matchmaker = String::from("flexible");
+        QueueTracker::create(tracker.clone(), String::from("test"), matchmaker, settings, None, tick_mode, false)
+            .await
+            .unwrap();
+
+        let player = Uuid::new_v4();
+        let mut guard = tracker.lock().await;
+        let receiver = guard.join("test", entry(vec![player]), PlayerData::default()).await.unwrap();
+        guard.join("test", entry(vec![Uuid::new_v4()]), PlayerData::default()).await.unwrap();
+        drop(guard);
+        QueueTracker::tick_task(tracker.clone(), "test").await;
+
+        let err = receiver.await.unwrap().unwrap_err();
+        assert_eq!(err.code(), "GAME_NOT_FOUND");
+
+        let filter = MatchFilter {
+            player: Some(player),
+            ..Default::default()
+        };
+        let matches = storage.get_matches(&filter).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].queue, "test");
+        assert_eq!(matches[0].teams.len(), 2);
+        assert_eq!(matches[0].game, Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_join_looks_up_rating() {
+        let storage = storage();
+        let tracker = Arc::new(Mutex::new(QueueTracker::new(GameFinder::default(), storage.clone(), profiles())));
+        let settings = json!({"scalingFactor": 1.0, "teamSize": 1, "maxSkillDiff": 200});
+        let (name, matchmaker) = (String::from("ranked"), String::from("elo"));
+        QueueTracker::create(tracker.clone(), name, matchmaker, settings, None, TickMode::default(), false)
+            .await
+            .unwrap();
+
+        let player = Uuid::new_v4();
+        let mut rating = PlayerRating::new(player, "ranked");
+        rating.elo = 1337.0;
+        let record = MatchRecord::new("ranked", &[vec![entry(vec![player])]], json!({}), None);
+        storage.save_match(&record).unwrap();
+        storage.save_result(record.id, &[1], &[rating]).unwrap();
+
+        // The client's own rating is never trusted
+        let mut joined = entry(vec![player]);
+        joined.metadata.insert(String::from("elo"), json!(3000));
+        let players = QueueTracker::lookup(&tracker, "ranked", &joined.players).await.unwrap();
+        let mut tracker = tracker.lock().await;
+        tracker.join("ranked", joined.clone(), players).await.unwrap();
+        let mut newcomer = entry(vec![Uuid::new_v4()]);
+        newcomer.metadata.insert(String::from("elo"), json!(3000));
+        tracker.join("ranked", newcomer.clone(), PlayerData::default()).await.unwrap();
+
+        let queue = tracker.get_queue("ranked").await.unwrap();
+        let queue = queue.lock().await;
+        assert_eq!(queue.entries()[&joined.id].metadata["elo"], json!(1337));
+        assert_eq!(queue.entries()[&newcomer.id].metadata["elo"], json!(1000));
+    }
+
+    #[tokio::test]
+    async fn test_join_roles_without_profile() {
+        let tracker = Arc::new(Mutex::new(QueueTracker::new(GameFinder::default(), storage(), profiles())));
+        let settings = json!({"roles": {"tank": 1, "damage": 1}});
+        let (name, matchmaker) = (String::from("roles"), String::from("roles"));
+        QueueTracker::create(tracker.clone(), name, matchmaker, settings, None, TickMode::default(), false)
+            .await
+            .unwrap();
+
+        // Players without a profile choose their own roles
+        let mut joined = entry(vec![Uuid::new_v4()]);
+        joined.metadata.insert(String::from("roles"), json!(["tank"]));
+        let players = QueueTracker::lookup(&tracker, "roles", &joined.players).await.unwrap();
+        let mut tracker = tracker.lock().await;
+        tracker.join("roles", joined.clone(), players).await.unwrap();
+
+        let queue = tracker.get_queue("roles").await.unwrap();
+        let queue = queue.lock().await;
+        assert_eq!(queue.entries()[&joined.id].metadata["roles"], json!(["tank"]));
+    }
+
+    #[tokio::test]
+    async fn test_join_merges_profile() {
+        let profiles = profiles();
+        let tracker = Arc::new(Mutex::new(QueueTracker::new(GameFinder::default(), storage(), profiles.clone())));
+        let settings = json!({"scalingFactor": 1.0, "teamSize": 2, "maxSkillDiff": 200});
+        let (name, matchmaker) = (String::from("ranked"), String::from("elo"));
+        QueueTracker::create(tracker.clone(), name, matchmaker, settings, None, TickMode::default(), false)
+            .await
+            .unwrap();
+
+        let mut profile = PlayerProfile::new(Uuid::new_v4());
+        profile.rating = Some(1800.0);
+        profiles.save_profile(&profile).unwrap();
+
+        let mut joined = entry(vec![profile.player]);
+        joined.metadata.insert(String::from("elo"), json!(3000));
+        let players = QueueTracker::lookup(&tracker, "ranked", &joined.players).await.unwrap();
+        let mut tracker = tracker.lock().await;
+        tracker.join("ranked", joined.clone(), players).await.unwrap();
+
+        // Only the player with a profile gets its roles
+        let mut party = entry(vec![Uuid::new_v4(), Uuid::new_v4()]);
+        party.metadata.insert(String::from("elo"), json!([3000, 3000]));
+        party.metadata.insert(String::from("roles"), json!([["tank"], ["tank"]]));
+        let mut party_profile = PlayerProfile::new(party.players[0]);
+        party_profile.roles = vec![String::from("healer")];
+        let players = PlayerData { profiles: vec![party_profile], ..PlayerData::default() };
+        tracker.join("ranked", party.clone(), players).await.unwrap();
+
+        let queue = tracker.get_queue("ranked").await.unwrap();
+        let queue = queue.lock().await;
+        assert_eq!(queue.entries()[&joined.id].metadata["elo"], json!(1800));
+        assert_eq!(queue.entries()[&party.id].metadata["elo"], json!([1000, 1000]));
+        assert_eq!(queue.entries()[&party.id].metadata["roles"], json!([["healer"], ["tank"]]));
+    }
+
+    #[tokio::test]
+    async fn test_paused_queue_rejects_joins() {
+        let tracker = tracker().await;
+        let mut tracker = tracker.lock().await;
+
+        tracker.pause("test", true, false).await.unwrap();
+        let err = tracker.join("test", entry(vec![Uuid::new_v4()]), PlayerData::default()).await.unwrap_err();
+        assert_eq!(err.code(), "QUEUE_PAUSED");
+
+        tracker.resume("test", false).await.unwrap();
+        assert!(tracker.join("test", entry(vec![Uuid::new_v4()]), PlayerData::default()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_update_evicts_entries() {
+        let tracker = tracker().await;
+        let mut tracker = tracker.lock().await;
+
+        let mut solo = entry(vec![Uuid::new_v4()]);
+        solo.time_queued -= chrono::Duration::seconds(30);
+        let time_queued = solo.time_queued;
+        let solo_id = solo.id;
+        tracker.join("test", solo, PlayerData::default()).await.unwrap();
+        let receiver = tracker
+            .join("test", entry(vec![Uuid::new_v4(), Uuid::new_v4()]), PlayerData::default())
+            .await
+            .unwrap();
+
+        let settings = json!({"numberOfTeams": 2, "teamSize": 5, "minEntrySize": 1, "maxEntrySize": 1});
+        let evicted = tracker.update("test", Some(settings), None, false).await.unwrap();
+
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].1.code(), "WRONG_TEAM_SIZE");
+        assert_eq!(receiver.await.unwrap().unwrap_err().code(), "ENTRY_EVICTED");
+
+        let queue = tracker.get_queue("test").await.unwrap();
+        let queue = queue.lock().await;
+        assert_eq!(queue.entries().len(), 1);
+        assert_eq!(queue.matchmaker().get_entry(&solo_id).unwrap().time_queued, time_queued);
+    }
+
+    #[tokio::test]
+    async fn test_update_rejects_invalid_settings() {
+        let tracker = tracker().await;
+        let mut tracker = tracker.lock().await;
+
+        let err = tracker.update("test", Some(json!({"teamSize": 0})), None, false).await.unwrap_err();
+        assert_eq!(err.code(), "INVALID_SETTINGS");
+        assert!(tracker.get_queue("test").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_update_tick_mode() {
+        let tracker = tracker().await;
+        let mut tracker = tracker.lock().await;
+        let tick_mode = TickMode::Event {
+            debounce_ms: 100,
+            max_interval_ms: 1000,
+        };
+
+        let invalid = TickMode::Interval { interval_ms: 0 };
+        let settings = json!({"numberOfTeams": 2, "teamSize": 2, "minEntrySize": 1, "maxEntrySize": 2});
+        let err = tracker.update("test", Some(settings), Some(invalid), false).await.unwrap_err();
+        assert_eq!(err.code(), "INVALID_SETTINGS");
+
+        tracker.update("test", None, Some(tick_mode.clone()), false).await.unwrap();
+
+        let queue = tracker.get_queue("test").await.unwrap();
+        let queue = queue.lock().await;
+        assert_eq!(queue.tick_mode(), &tick_mode);
+        assert_eq!(queue.matchmaker().serialize().unwrap()["teamSize"], json!(5));
+    }
+
+    #[tokio::test]
+    async fn test_delete_notifies_waiting_entries() {
+        let tracker = tracker().await;
+        let mut tracker = tracker.lock().await;
+
+        let receiver = tracker.join("test", entry(vec![Uuid::new_v4()]), PlayerData::default()).await.unwrap();
+        tracker.delete("test", false, false).await.unwrap();
+
+        let err = receiver.await.unwrap().unwrap_err();
+        assert_eq!(err.code(), "QUEUE_CLOSED");
+        assert!(tracker.get_queue("test").await.is_none());
+
+        let err = tracker.delete("test", false, false).await.unwrap_err();
+        assert_eq!(err.code(), "QUEUE_NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn test_delete_lets_game_lookups_finish() {
+        // Holds the game request until the queue is deleted, then fails it
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut game_finder = GameFinder::default();
+        game_finder.config.base_url = format!("http://{}/{{playlist}}", listener.local_addr().unwrap());
+        let (requested_tx, requested_rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let _ = requested_tx.send(());
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            drop(socket);
+        });
+
+        let tracker = Arc::new(Mutex::new(QueueTracker::new(game_finder, storage(), profiles())));
+        let settings = json!({"numberOfTeams": 2, "teamSize": 1, "minEntrySize": 1, "maxEntrySize": 1});
+        let tick_mode = TickMode::Interval { interval_ms: 10 };
+        let matchmaker = String::from("flexible");
+        QueueTracker::create(tracker.clone(), String::from("test"), matchmaker, settings, None, tick_mode, false)
+            .await
+            .unwrap();
+
+        let mut guard = tracker.lock().await;
+        let receiver = guard.join("test", entry(vec![Uuid::new_v4()]), PlayerData::default()).await.unwrap();
+        guard.join("test", entry(vec![Uuid::new_v4()]), PlayerData::default()).await.unwrap();
+        drop(guard);
+        requested_rx.await.unwrap();
+        tracker.lock().await.delete("test", false, false).await.unwrap();
+
+        let err = receiver.await.unwrap().unwrap_err();
+        assert_eq!(err.code(), "GAME_NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn test_drain_rejects_new_entries() {
+        let tracker = tracker().await;
+
+        let waiting = entry(vec![Uuid::new_v4()]);
+        let id = waiting.id;
+        let mut guard = tracker.lock().await;
+        guard.join("test", waiting, PlayerData::default()).await.unwrap();
+        guard.delete("test", true, false).await.unwrap();
+
+        let err = guard.join("test", entry(vec![Uuid::new_v4()]), PlayerData::default()).await.unwrap_err();
+        assert_eq!(err.code(), "QUEUE_CLOSED");
+        assert!(!QueueTracker::finish_drain_locked(&mut guard, "test").await);
+
+        guard.leave("test", id).await;
+        assert!(QueueTracker::finish_drain_locked(&mut guard, "test").await);
+        assert!(guard.get_queue("test").await.is_none());
+    }
+}
@@ -0,0 +1,507 @@
+use crate::algo::glicko2::Glicko2Rating;
+use crate::entry::Entry;
+use crate::leaderboard::FIRST_SEASON;
+use crate::storage::{Storage, StorageError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::sync::Mutex;
+use thiserror::Error;
+use uuid::Uuid;
+
+/// Elo of a player without any reported match
+pub const DEFAULT_ELO: f64 = 1000.0;
+/// Largest change of a player's Elo after a single match
+const ELO_K_FACTOR: f64 = 32.0;
+
+pub const DEFAULT_GLICKO: Glicko2Rating = Glicko2Rating {
+    rating: 1500.0,
+    rd: 350.0,
+    volatility: 0.06,
+};
+/// Constrains how much the volatility changes after a match
+const GLICKO_TAU: f64 = 0.5;
+/// Converts between the Glicko and Glicko-2 scales
+const GLICKO_SCALE: f64 = 173.7178;
+const GLICKO_EPSILON: f64 = 0.000001;
+
+/// Serializes reports, so two matches sharing a player don't both update the same rating.
+pub(crate) static REPORT_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Error, Debug)]
+pub enum ReportError {
+    #[error("Match {0} not found")]
+    MatchNotFound(Uuid),
+    #[error("The result of match {0} was already reported")]
+    AlreadyReported(Uuid),
+    #[error("Invalid match result: {0}")]
+    InvalidResult(String),
+    #[error(transparent)]
+    Storage(#[from] StorageError),
+}
+
+impl ReportError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ReportError::MatchNotFound(_) => "MATCH_NOT_FOUND",
+            ReportError::AlreadyReported(_) => "RESULT_ALREADY_REPORTED",
+            ReportError::InvalidResult(_) => "INVALID_RESULT",
+            ReportError::Storage(_) => "STORAGE_ERROR",
+        }
+    }
+}
+
+/// Rating of a player in a single queue, updated whenever a match result is reported.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerRating {
+    pub player: Uuid,
+    pub queue: String,
+    /// Season of the queue the rating belongs to
+    #[serde(default = "first_season")]
+    pub season: u32,
+    pub elo: f64,
+    pub glicko: Glicko2Rating,
+    /// Matches with a reported result
+    pub matches: u32,
+    /// Matches the player's team placed first in
+    pub wins: u32,
+    pub updated_at: DateTime<Utc>,
+}
+
+fn first_season() -> u32 {
+    FIRST_SEASON
+}
+
+impl PlayerRating {
+    pub fn new(player: Uuid, queue: &str) -> Self {
+        Self {
+            player,
+            queue: String::from(queue),
+            season: FIRST_SEASON,
+            elo: DEFAULT_ELO,
+            glicko: DEFAULT_GLICKO,
+            matches: 0,
+            wins: 0,
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+/// Outcome of a match as reported by the game server, either the index of the winning team or
+/// the place of every team.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchOutcome {
+    #[serde(default)]
+    pub winner: Option<usize>,
+    #[serde(default)]
+    pub placements: Option<Vec<u32>>,
+}
+
+impl MatchOutcome {
+    /// Place of each of the `teams` teams, the teams that didn't win share second place.
+    pub fn placements(&self, teams: usize) -> Result<Vec<u32>, ReportError> {
+        let placements = match (self.winner, &self.placements) {
+            (Some(winner), None) if winner < teams => {
+                (0..teams).map(|team| if team == winner { 1 } else { 2 }).collect()
+            }
+            (Some(winner), None) => {
+                return Err(ReportError::InvalidResult(format!(
+                    "Winner {} is not one of the {} teams",
+                    winner, teams
+                )));
+            }
+            (None, Some(placements)) => placements.clone(),
+            _ => {
+                return Err(ReportError::InvalidResult(String::from(
+                    "Either winner or placements must be set",
+                )));
+            }
+        };
+
+        if placements.len() != teams {
+            return Err(ReportError::InvalidResult(format!(
+                "Expected a placement for each of the {} teams, got {}",
+                teams,
+                placements.len()
+            )));
+        }
+        if placements.contains(&0) {
+            return Err(ReportError::InvalidResult(String::from("Placements start at 1")));
+        }
+        Ok(placements)
+    }
+}
+
+/// Score of a team placed `a` against a team placed `b`, lower placements are better.
+fn score(a: u32, b: u32) -> f64 {
+    match a.cmp(&b) {
+        std::cmp::Ordering::Less => 1.0,
+        std::cmp::Ordering::Equal => 0.5,
+        std::cmp::Ordering::Greater => 0.0,
+    }
+}
+
+fn average(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), x| (sum + x, count + 1));
+    if count == 0 { 0.0 } else { sum / count as f64 }
+}
+
+/// A team rated as a single Glicko-2 opponent: the average rating, with the deviations
+/// combined as if the players' ratings were independent.
+fn team_glicko(team: &[PlayerRating]) -> Glicko2Rating {
+    Glicko2Rating {
+        rating: average(team.iter().map(|x| x.glicko.rating)),
+        rd: average(team.iter().map(|x| x.glicko.rd.powi(2))).sqrt(),
+        volatility: average(team.iter().map(|x| x.glicko.volatility)),
+    }
+}
+
+/// Glicko-2 update of `player` after playing every opponent once, following Glickman's
+/// "Example of the Glicko-2 system".
+fn update_glicko(player: Glicko2Rating, opponents: &[(Glicko2Rating, f64)]) -> Glicko2Rating {
+    let mu = (player.rating - 1500.0) / GLICKO_SCALE;
+    let phi = player.rd / GLICKO_SCALE;
+    let sigma = player.volatility;
+
+    let mut inverse_v = 0.0;
+    let mut improvement = 0.0;
+    for (opponent, score) in opponents {
+        let mu_j = (opponent.rating - 1500.0) / GLICKO_SCALE;
+        let phi_j = opponent.rd / GLICKO_SCALE;
+        let g = 1.0 / (1.0 + 3.0 * phi_j.powi(2) / PI.powi(2)).sqrt();
+        let expected = 1.0 / (1.0 + (-g * (mu - mu_j)).exp());
+
+        inverse_v += g.powi(2) * expected * (1.0 - expected);
+        improvement += g * (score - expected);
+    }
+    if opponents.is_empty() || inverse_v == 0.0 {
+        return player;
+    }
+    let v = 1.0 / inverse_v;
+    let delta = v * improvement;
+
+    // New volatility, found with the Illinois algorithm
+    let a = sigma.powi(2).ln();
+    let f = |x: f64| {
+        let ex = x.exp();
+        ex * (delta.powi(2) - phi.powi(2) - v - ex) / (2.0 * (phi.powi(2) + v + ex).powi(2))
+            - (x - a) / GLICKO_TAU.powi(2)
+    };
+    let mut lower = a;
+    let mut upper = if delta.powi(2) > phi.powi(2) + v {
+        (delta.powi(2) - phi.powi(2) - v).ln()
+    } else {
+        let mut k = 1.0;
+        while f(a - k * GLICKO_TAU) < 0.0 {
+            k += 1.0;
+        }
+        a - k * GLICKO_TAU
+    };
+    let mut f_lower = f(lower);
+    let mut f_upper = f(upper);
+    while (upper - lower).abs() > GLICKO_EPSILON {
+        let c = lower + (lower - upper) * f_lower / (f_upper - f_lower);
+        let f_c = f(c);
+        if f_c * f_upper <= 0.0 {
+            lower = upper;
+            f_lower = f_upper;
+        } else {
+            f_lower /= 2.0;
+        }
+        upper = c;
+        f_upper = f_c;
+    }
+    let volatility = (lower / 2.0).exp();
+
+    let phi_star = (phi.powi(2) + volatility.powi(2)).sqrt();
+    let new_phi = 1.0 / (1.0 / phi_star.powi(2) + 1.0 / v).sqrt();
+    let new_mu = mu + new_phi.powi(2) * improvement;
+
+    Glicko2Rating {
+        rating: new_mu * GLICKO_SCALE + 1500.0,
+        rd: new_phi * GLICKO_SCALE,
+        volatility,
+    }
+}
+
+/// New ratings of every player after a match, `placements` holds the place of each team with
+/// 1 being the best. Teams sharing a place drew against each other.
+///
+/// Every other team counts as a single opponent, rated by its players' average, and every
+/// player on a team gains or loses the same Elo.
+pub fn rate(teams: &[Vec<PlayerRating>], placements: &[u32]) -> Vec<PlayerRating> {
+    let best = placements.iter().copied().min().unwrap_or(1);
+    let team_elos: Vec<f64> = teams.iter().map(|team| average(team.iter().map(|x| x.elo))).collect();
+    let team_glickos: Vec<Glicko2Rating> = teams.iter().map(|team| team_glicko(team)).collect();
+    let now = Utc::now();
+
+    let mut ratings: Vec<PlayerRating> = Vec::new();
+    for (i, team) in teams.iter().enumerate() {
+        let opponents: Vec<usize> = (0..teams.len()).filter(|j| *j != i).collect();
+
+        let elo_change = if opponents.is_empty() {
+            0.0
+        } else {
+            let total: f64 = opponents
+                .iter()
+                .map(|j| {
+                    let expected = 1.0 / (1.0 + 10f64.powf((team_elos[*j] - team_elos[i]) / 400.0));
+                    score(placements[i], placements[*j]) - expected
+                })
+                .sum();
+            ELO_K_FACTOR * total / opponents.len() as f64
+        };
+        let glicko_opponents: Vec<(Glicko2Rating, f64)> = opponents
+            .iter()
+            .map(|j| (team_glickos[*j], score(placements[i], placements[*j])))
+            .collect();
+
+        for player in team {
+            ratings.push(PlayerRating {
+                elo: player.elo + elo_change,
+                glicko: update_glicko(player.glicko, &glicko_opponents),
+                matches: player.matches + 1,
+                wins: player.wins + u32::from(placements[i] == best),
+                updated_at: now,
+                ..player.clone()
+            });
+        }
+    }
+    ratings
+}
+
+/// Metadata keys of a Glicko-2 rating, see [`Glicko2Matchmaker`](crate::algo::glicko2::Glicko2Matchmaker)
+const GLICKO_KEYS: [&str; 3] = ["rating", "rd", "volatility"];
+
+/// Whether the matchmaker requiring the metadata keys `required` matches by rating, in which
+/// case the stored ratings of joining players are looked up.
+pub fn uses_ratings(required: &[String]) -> bool {
+    required.iter().any(|key| key == "elo" || GLICKO_KEYS.contains(&key.as_str()))
+}
+
+/// Sets the ratings the matchmaker requires to the stored ratings of the entry's players,
+/// replacing whatever the client sent. Players without a reported match start at the default
+/// rating.
+///
+/// `elo` is set per player for parties, a Glicko-2 rating is set for the party as a whole as the
+/// Glicko-2 matchmaker only accepts one rating per entry.
+pub fn fill_metadata(entry: &mut Entry, queue: &str, required: &[String], stored: Vec<PlayerRating>) {
+    let mut stored: HashMap<Uuid, PlayerRating> = stored.into_iter().map(|x| (x.player, x)).collect();
+    let ratings: Vec<PlayerRating> = entry
+        .players
+        .iter()
+        .map(|player| stored.remove(player).unwrap_or_else(|| PlayerRating::new(*player, queue)))
+        .collect();
+
+    if required.iter().any(|x| x == "elo") {
+        let elos: Vec<i64> = ratings.iter().map(|x| x.elo.round() as i64).collect();
+        let elo = match elos.as_slice() {
+            [elo] => json!(elo),
+            elos => json!(elos),
+        };
+        entry.metadata.insert(String::from("elo"), elo);
+    }
+
+    if GLICKO_KEYS.iter().any(|key| required.iter().any(|x| x == key)) {
+        let glicko = team_glicko(&ratings);
+        entry.metadata.insert(String::from("rating"), json!(glicko.rating));
+        entry.metadata.insert(String::from("rd"), json!(glicko.rd));
+        entry.metadata.insert(String::from("volatility"), json!(glicko.volatility));
+    }
+}
+
+/// Stores the result of a match and updates the rating of every player in it. A match's
+/// result can only be reported once.
+///
+/// Only game servers should report results, as any caller knowing a match id can decide its
+/// outcome. The HTTP endpoint requires the `RESULT_TOKEN`.
+pub fn report_result(
+    storage: &dyn Storage,
+    match_id: Uuid,
+    outcome: &MatchOutcome,
+) -> Result<Vec<PlayerRating>, ReportError> {
+    let _guard = REPORT_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+
+    let record = storage.get_match(match_id)?.ok_or(ReportError::MatchNotFound(match_id))?;
+    if record.placements.is_some() {
+        return Err(ReportError::AlreadyReported(match_id));
+    }
+    if record.game.is_null() {
+        return Err(ReportError::InvalidResult(String::from("No game was found for the match")));
+    }
+    let placements = outcome.placements(record.teams.len())?;
+
+    let season = storage.current_season(&record.queue)?.number;
+    let players: Vec<Uuid> = record.players().copied().collect();
+    let mut stored: HashMap<Uuid, PlayerRating> = storage
+        .get_ratings(&record.queue, season, &players)?
+        .into_iter()
+        .map(|rating| (rating.player, rating))
+        .collect();
+    let teams: Vec<Vec<PlayerRating>> = record
+        .teams
+        .iter()
+        .map(|team| {
+            team.iter()
+                .flat_map(|entry| &entry.players)
+                .map(|player| {
+                    stored
+                        .remove(player)
+                        .unwrap_or_else(|| PlayerRating {
+                            season,
+                            ..PlayerRating::new(*player, &record.queue)
+                        })
+                })
+                .collect()
+        })
+        .collect();
+
+    let ratings = rate(&teams, &placements);
+    storage
+        .save_result(match_id, &placements, &ratings)
+        .map_err(|err| match err {
+            StorageError::AlreadyReported(id) => ReportError::AlreadyReported(id),
+            err => ReportError::Storage(err),
+        })?;
+    Ok(ratings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MatchRecord;
+    use crate::storage::json::JsonStorage;
+
+    fn player(elo: f64, glicko: Glicko2Rating) -> PlayerRating {
+        PlayerRating {
+            elo,
+            glicko,
+            ..PlayerRating::new(Uuid::new_v4(), "ranked")
+        }
+    }
+
+    #[test]
+    fn test_elo_winner_gains() {
+        let teams = vec![
+            vec![player(1000.0, DEFAULT_GLICKO)],
+            vec![player(1000.0, DEFAULT_GLICKO)],
+        ];
+        let ratings = rate(&teams, &[1, 2]);
+
+        assert_eq!(ratings[0].elo, 1016.0);
+        assert_eq!(ratings[1].elo, 984.0);
+        assert_eq!((ratings[0].wins, ratings[0].matches), (1, 1));
+        assert_eq!((ratings[1].wins, ratings[1].matches), (0, 1));
+    }
+
+    #[test]
+    fn test_elo_upset_gains_more() {
+        let teams = vec![
+            vec![player(1200.0, DEFAULT_GLICKO)],
+            vec![player(1000.0, DEFAULT_GLICKO)],
+        ];
+        let favourite_wins = rate(&teams, &[1, 2]);
+        let underdog_wins = rate(&teams, &[2, 1]);
+
+        assert!(underdog_wins[1].elo - 1000.0 > favourite_wins[0].elo - 1200.0);
+
+        let draw = rate(&teams, &[1, 1]);
+        assert!(draw[0].elo < 1200.0);
+        assert!(draw[1].elo > 1000.0);
+    }
+
+    #[test]
+    fn test_glicko_example() {
+        // The example from Glickman's paper: a win against the first opponent, losses against
+        // the others
+        let rating = |rating, rd| Glicko2Rating {
+            rating,
+            rd,
+            volatility: 0.06,
+        };
+        let updated = update_glicko(
+            rating(1500.0, 200.0),
+            &[(rating(1400.0, 30.0), 1.0), (rating(1550.0, 100.0), 0.0), (rating(1700.0, 300.0), 0.0)],
+        );
+
+        assert!((updated.rating - 1464.06).abs() < 0.01);
+        assert!((updated.rd - 151.52).abs() < 0.01);
+        assert!((updated.volatility - 0.05999).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_fill_metadata() {
+        let required = vec![String::from("elo")];
+        let stored = player(1234.4, DEFAULT_GLICKO);
+        let mut entry = Entry::new(Uuid::new_v4(), vec![stored.player, Uuid::new_v4()], Default::default());
+
+        assert!(uses_ratings(&required));
+        fill_metadata(&mut entry, "ranked", &required, vec![stored]);
+        assert_eq!(entry.metadata["elo"], json!([1234, 1000]));
+        assert!(!entry.metadata.contains_key("rating"));
+
+        // Ratings sent by the client are replaced
+        let required = vec![String::from("rating"), String::from("rd"), String::from("volatility")];
+        let mut entry = Entry::new(Uuid::new_v4(), vec![Uuid::new_v4()], Default::default());
+        entry.metadata.insert(String::from("rating"), json!(1800.0));
+        fill_metadata(&mut entry, "ranked", &required, Vec::new());
+        assert_eq!(entry.metadata["rating"], json!(1500.0));
+        assert_eq!(entry.metadata["rd"], json!(350.0));
+
+        assert!(!uses_ratings(&[String::from("roles")]));
+    }
+
+    #[test]
+    fn test_report_result() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory).unwrap();
+        let storage = JsonStorage::new(directory);
+
+        let entries: Vec<Vec<Entry>> = (0..2)
+            .map(|_| vec![Entry::new(Uuid::new_v4(), vec![Uuid::new_v4()], Default::default())])
+            .collect();
+        let record = MatchRecord::new("ranked", &entries, json!({"host": "127.0.0.1"}), None);
+        storage.save_match(&record).unwrap();
+
+        let outcome = |placements: Vec<u32>| MatchOutcome {
+            winner: None,
+            placements: Some(placements),
+        };
+        let err = report_result(&storage, record.id, &outcome(vec![1])).unwrap_err();
+        assert_eq!(err.code(), "INVALID_RESULT");
+        let err = report_result(&storage, record.id, &MatchOutcome::default()).unwrap_err();
+        assert_eq!(err.code(), "INVALID_RESULT");
+
+        let winner = MatchOutcome {
+            winner: Some(1),
+            placements: None,
+        };
+        let ratings = report_result(&storage, record.id, &winner).unwrap();
+        assert_eq!(ratings[1].elo, 1016.0);
+        let winner = entries[1][0].players[0];
+        assert_eq!(storage.get_ratings("ranked", FIRST_SEASON, &[winner]).unwrap()[0].wins, 1);
+
+        assert_eq!(storage.get_match(record.id).unwrap().unwrap().placements, Some(vec![2, 1]));
+
+        let err = report_result(&storage, record.id, &outcome(vec![2, 1])).unwrap_err();
+        assert_eq!(err.code(), "RESULT_ALREADY_REPORTED");
+        let err = report_result(&storage, Uuid::new_v4(), &outcome(vec![2, 1])).unwrap_err();
+        assert_eq!(err.code(), "MATCH_NOT_FOUND");
+    }
+
+    #[test]
+    fn test_party_shares_elo_change() {
+        let teams = vec![
+            vec![player(900.0, DEFAULT_GLICKO), player(1100.0, DEFAULT_GLICKO)],
+            vec![player(1000.0, DEFAULT_GLICKO), player(1000.0, DEFAULT_GLICKO)],
+        ];
+        let ratings = rate(&teams, &[1, 2]);
+
+        assert_eq!(ratings[0].elo - 900.0, ratings[1].elo - 1100.0);
+        assert_eq!(ratings[0].elo - 900.0, 16.0);
+    }
+}
@@ -0,0 +1,146 @@
+use jsonschema::Validator;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::error::Error;
+use thiserror::Error;
+
+/// A single metadata field that didn't match the schema.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldError {
+    /// JSON pointer to the field, e.g. `/elo`, empty for the metadata object itself
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("Invalid metadata: {}", describe(.errors))]
+pub struct MetadataError {
+    pub errors: Vec<FieldError>,
+}
+
+fn describe(errors: &[FieldError]) -> String {
+    errors
+        .iter()
+        .map(|error| match error.path.as_str() {
+            "" => error.message.clone(),
+            path => format!("{}: {}", path, error.message),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Validates entry metadata against the schema its matchmaker requires and, optionally, a
+/// schema configured on the queue.
+pub struct MetadataSchema {
+    schema: Option<Value>,
+    validators: Vec<Validator>,
+}
+
+impl MetadataSchema {
+    pub fn new(matchmaker_schema: Value, schema: Option<Value>) -> Result<Self, Box<dyn Error>> {
+        let mut validators = vec![
+            jsonschema::validator_for(&matchmaker_schema)
+                .map_err(|err| format!("Invalid matchmaker metadata schema: {}", err))?,
+        ];
+        if let Some(schema) = &schema {
+            validators.push(
+                jsonschema::validator_for(schema)
+                    .map_err(|err| format!("Invalid metadata schema: {}", err))?,
+            );
+        }
+
+        Ok(Self { schema, validators })
+    }
+
+    /// The schema configured on the queue, without the matchmaker's requirements.
+    pub fn schema(&self) -> Option<&Value> {
+        self.schema.as_ref()
+    }
+
+    pub fn validate(&self, metadata: &Map<String, Value>) -> Result<(), MetadataError> {
+        let metadata = Value::Object(metadata.clone());
+
+        let mut errors: Vec<FieldError> = Vec::new();
+        for validator in &self.validators {
+            for error in validator.iter_errors(&metadata) {
+                let field_error = FieldError {
+                    path: error.instance_path.to_string(),
+                    message: error.to_string(),
+                };
+                // Both schemas may reject the same field for the same reason
+                if !errors.contains(&field_error) {
+                    errors.push(field_error);
+                }
+            }
+        }
+
+        if errors.is_empty() { Ok(()) } else { Err(MetadataError { errors }) }
+    }
+}
+
+/// Metadata keys `schema` requires, including those required through `allOf`.
+pub fn required_keys(schema: &Value) -> Vec<String> {
+    let mut keys: Vec<String> = schema
+        .get("required")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|key| key.as_str().map(String::from))
+        .collect();
+
+    for inner in schema.get("allOf").and_then(Value::as_array).into_iter().flatten() {
+        keys.extend(required_keys(inner));
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn metadata(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_matchmaker_schema() {
+        let schema = MetadataSchema::new(
+            json!({"type": "object", "required": ["elo"], "properties": {"elo": {"type": "integer"}}}),
+            None,
+        )
+        .unwrap();
+
+        assert!(schema.validate(&metadata(json!({"elo": 1000}))).is_ok());
+
+        let error = schema.validate(&metadata(json!({"elo": "high"}))).unwrap_err();
+        assert_eq!(error.errors.len(), 1);
+        assert_eq!(error.errors[0].path, "/elo");
+
+        let error = schema.validate(&metadata(json!({}))).unwrap_err();
+        assert_eq!(error.errors[0].path, "");
+    }
+
+    #[test]
+    fn test_queue_schema() {
+        let schema = MetadataSchema::new(
+            json!({"type": "object"}),
+            Some(json!({"properties": {"platform": {"enum": ["pc", "console"]}}})),
+        )
+        .unwrap();
+
+        assert!(schema.validate(&metadata(json!({"platform": "pc"}))).is_ok());
+        assert!(schema.validate(&metadata(json!({"platform": "fridge"}))).is_err());
+    }
+
+    #[test]
+    fn test_required_keys() {
+        let schema = json!({"allOf": [{"required": ["pings"]}, {"type": "object", "required": ["elo"]}]});
+        assert_eq!(required_keys(&schema), vec!["pings", "elo"]);
+    }
+
+    #[test]
+    fn test_invalid_schema_rejected() {
+        assert!(MetadataSchema::new(json!({"type": "object"}), Some(json!({"type": 5}))).is_err());
+    }
+}
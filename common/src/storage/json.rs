@@ -0,0 +1,475 @@
+use crate::leaderboard::{RatingOrder, Season};
+use crate::profile::{PlayerProfile, ProfileStore};
+use crate::rating::PlayerRating;
+use crate::storage::{MatchFilter, MatchRecord, QueueDefinition, Storage, StorageError};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Keeps queues, matches, ratings, seasons and profiles in `queues.json`, `matches.jsonl`,
+/// `ratings.json`, `seasons.json` and `profiles.json`.
+///
+/// Formed matches are appended to `matches.jsonl`, one per line, but reporting a result rewrites
+/// the file. Busy servers should use a database backend.
+pub struct JsonStorage {
+    directory: PathBuf,
+    /// Matches, ratings, seasons and profiles are changed by reading and rewriting their files
+    lock: Mutex<()>,
+}
+
+/// Writes to a temporary file next to `path` and renames it over `path`, so a crash mid-write
+/// leaves either the old or the new file but never a truncated one.
+fn write_atomic(path: &Path, data: &str) -> Result<(), StorageError> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let mut file = std::fs::File::create(&temp_path)?;
+    file.write_all(data.as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+impl JsonStorage {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn queues_path(&self) -> PathBuf {
+        self.directory.join("queues.json")
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, ()>, StorageError> {
+        self.lock
+            .lock()
+            .map_err(|_| StorageError::Database(String::from("Lock poisoned")))
+    }
+
+    /// Reads a list from `file`, which is empty if the file doesn't exist yet.
+    fn read_list<T: DeserializeOwned>(&self, file: &str) -> Result<Vec<T>, StorageError> {
+        match std::fs::read_to_string(self.directory.join(file)) {
+            Ok(data) => Ok(serde_json::from_str(&data)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn write_list<T: Serialize>(&self, file: &str, values: &[T]) -> Result<(), StorageError> {
+        write_atomic(&self.directory.join(file), &serde_json::to_string(values)?)
+    }
+
+    fn matches_path(&self) -> PathBuf {
+        self.directory.join("matches.jsonl")
+    }
+
+    /// Reads every match, skipping lines that can't be parsed, e.g. one cut short by a crash.
+    fn read_matches(&self) -> Result<Vec<MatchRecord>, StorageError> {
+        let data = match std::fs::read_to_string(self.matches_path()) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut matches: Vec<MatchRecord> = Vec::new();
+        for line in data.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str(line) {
+                Ok(record) => matches.push(record),
+                Err(err) => warn!("Invalid match in {}, skipping: {}", self.matches_path().display(), err),
+            }
+        }
+        Ok(matches)
+    }
+
+    /// Appends a match as a new line, without reading or rewriting the others.
+    fn append_match(&self, record: &MatchRecord) -> Result<(), StorageError> {
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(self.matches_path())?;
+
+        // Start on a new line if the last append was cut short
+        let mut line = String::new();
+        if file.metadata()?.len() > 0 {
+            let mut last = [0; 1];
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+            if last[0] != b'\n' {
+                line.push('\n');
+            }
+        }
+        line.push_str(&serde_json::to_string(record)?);
+        line.push('\n');
+
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    fn write_matches(&self, matches: &[MatchRecord]) -> Result<(), StorageError> {
+        let mut data = String::new();
+        for record in matches {
+            data.push_str(&serde_json::to_string(record)?);
+            data.push('\n');
+        }
+        write_atomic(&self.matches_path(), &data)
+    }
+}
+
+impl Storage for JsonStorage {
+    fn load_queues(&self) -> Result<Vec<QueueDefinition>, StorageError> {
+        let queues_path = self.queues_path();
+        let data = match std::fs::read_to_string(&queues_path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                info!("No {} file found, starting without queues", queues_path.display());
+                write_atomic(&queues_path, "[]")?;
+                return Ok(Vec::new());
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        // A broken queue is skipped instead of losing every other queue
+        let mut queues: Vec<QueueDefinition> = Vec::new();
+        for value in serde_json::from_str::<Vec<Value>>(&data)? {
+            match serde_json::from_value::<QueueDefinition>(value) {
+                Ok(queue) => queues.push(queue),
+                Err(err) => warn!("Invalid queue in {}, skipping: {}", queues_path.display(), err),
+            }
+        }
+        Ok(queues)
+    }
+
+    fn save_queues(&self, queues: &[QueueDefinition]) -> Result<(), StorageError> {
+        write_atomic(&self.queues_path(), &serde_json::to_string_pretty(queues)?)?;
+        Ok(())
+    }
+
+    fn save_match(&self, record: &MatchRecord) -> Result<(), StorageError> {
+        let _guard = self.lock()?;
+
+        self.append_match(record)
+    }
+
+    fn get_matches(&self, filter: &MatchFilter) -> Result<Vec<MatchRecord>, StorageError> {
+        let _guard = self.lock()?;
+
+        let mut matches: Vec<MatchRecord> = self
+            .read_matches()?
+            .into_iter()
+            .filter(|record| filter.matches(record))
+            .collect();
+        matches.sort_by_key(|record| std::cmp::Reverse(record.created_at));
+        matches.truncate(filter.limit.unwrap_or(usize::MAX));
+        Ok(matches)
+    }
+
+    fn get_match(&self, id: Uuid) -> Result<Option<MatchRecord>, StorageError> {
+        let _guard = self.lock()?;
+
+        let matches = self.read_matches()?;
+        Ok(matches.into_iter().find(|record| record.id == id))
+    }
+
+    fn save_result(&self, id: Uuid, placements: &[u32], ratings: &[PlayerRating]) -> Result<(), StorageError> {
+        let _guard = self.lock()?;
+
+        let mut matches = self.read_matches()?;
+        let Some(record) = matches.iter_mut().find(|record| record.id == id) else {
+            return Err(StorageError::Database(format!("Match {} not found", id)));
+        };
+        if record.placements.is_some() {
+            return Err(StorageError::AlreadyReported(id));
+        }
+        record.placements = Some(placements.to_vec());
+
+        let mut stored: Vec<PlayerRating> = self.read_list("ratings.json")?;
+        stored.retain(|x| {
+            !ratings
+                .iter()
+                .any(|rating| rating.player == x.player && rating.queue == x.queue && rating.season == x.season)
+        });
+        stored.extend_from_slice(ratings);
+
+        // Mark the match as reported first, so a crash in between can't let it be reported again
+        self.write_matches(&matches)?;
+        self.write_list("ratings.json", &stored)
+    }
+
+    fn get_ratings(&self, queue: &str, season: u32, players: &[Uuid]) -> Result<Vec<PlayerRating>, StorageError> {
+        let _guard = self.lock()?;
+
+        let ratings: Vec<PlayerRating> = self.read_list("ratings.json")?;
+        Ok(ratings
+            .into_iter()
+            .filter(|rating| rating.queue == queue && rating.season == season && players.contains(&rating.player))
+            .collect())
+    }
+
+    fn current_season(&self, queue: &str) -> Result<Season, StorageError> {
+        let _guard = self.lock()?;
+
+        let seasons: Vec<Season> = self.read_list("seasons.json")?;
+        Ok(seasons
+            .into_iter()
+            .find(|season| season.queue == queue)
+            .unwrap_or_else(|| Season::first(queue)))
+    }
+
+    fn start_season(&self, queue: &str) -> Result<Season, StorageError> {
+        let _guard = self.lock()?;
+
+        let mut seasons: Vec<Season> = self.read_list("seasons.json")?;
+        let current = seasons
+            .iter()
+            .position(|season| season.queue == queue)
+            .map(|index| seasons.remove(index))
+            .unwrap_or_else(|| Season::first(queue));
+        let season = Season {
+            queue: String::from(queue),
+            number: current.number + 1,
+            started_at: Some(chrono::Utc::now()),
+        };
+
+        seasons.push(season.clone());
+        self.write_list("seasons.json", &seasons)?;
+        Ok(season)
+    }
+
+    fn get_leaderboard(
+        &self,
+        queue: &str,
+        season: u32,
+        order: RatingOrder,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<PlayerRating>, StorageError> {
+        let _guard = self.lock()?;
+
+        let mut ratings: Vec<PlayerRating> = self
+            .read_list::<PlayerRating>("ratings.json")?
+            .into_iter()
+            .filter(|rating| rating.queue == queue && rating.season == season)
+            .collect();
+        ratings.sort_by(|a, b| order.compare(a, b));
+        Ok(ratings.into_iter().skip(offset).take(limit).collect())
+    }
+
+    fn get_rank(
+        &self,
+        queue: &str,
+        season: u32,
+        order: RatingOrder,
+        player: Uuid,
+    ) -> Result<Option<(usize, PlayerRating)>, StorageError> {
+        let _guard = self.lock()?;
+
+        let ratings: Vec<PlayerRating> = self
+            .read_list::<PlayerRating>("ratings.json")?
+            .into_iter()
+            .filter(|rating| rating.queue == queue && rating.season == season)
+            .collect();
+        let Some(rating) = ratings.iter().find(|rating| rating.player == player) else {
+            return Ok(None);
+        };
+
+        let ahead = ratings.iter().filter(|x| order.compare(x, rating).is_lt()).count();
+        Ok(Some((ahead + 1, rating.clone())))
+    }
+}
+
+impl ProfileStore for JsonStorage {
+    fn get_profiles(&self, players: &[Uuid]) -> Result<Vec<PlayerProfile>, StorageError> {
+        let _guard = self.lock()?;
+
+        let profiles: Vec<PlayerProfile> = self.read_list("profiles.json")?;
+        Ok(profiles.into_iter().filter(|profile| players.contains(&profile.player)).collect())
+    }
+
+    fn list_profiles(&self, offset: usize, limit: usize) -> Result<Vec<PlayerProfile>, StorageError> {
+        let _guard = self.lock()?;
+
+        let profiles: Vec<PlayerProfile> = self.read_list("profiles.json")?;
+        Ok(profiles.into_iter().skip(offset).take(limit).collect())
+    }
+
+    fn save_profile(&self, profile: &PlayerProfile) -> Result<(), StorageError> {
+        let _guard = self.lock()?;
+
+        // Kept sorted by player, so listing pages through them in a stable order
+        let mut profiles: Vec<PlayerProfile> = self.read_list("profiles.json")?;
+        match profiles.binary_search_by_key(&profile.player, |x| x.player) {
+            Ok(index) => profiles[index] = profile.clone(),
+            Err(index) => profiles.insert(index, profile.clone()),
+        }
+        self.write_list("profiles.json", &profiles)
+    }
+
+    fn delete_profile(&self, player: Uuid) -> Result<bool, StorageError> {
+        let _guard = self.lock()?;
+
+        let mut profiles: Vec<PlayerProfile> = self.read_list("profiles.json")?;
+        let count = profiles.len();
+        profiles.retain(|profile| profile.player != player);
+        if profiles.len() == count {
+            return Ok(false);
+        }
+        self.write_list("profiles.json", &profiles)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::{Entry, EntryId};
+    use crate::leaderboard::FIRST_SEASON;
+    use crate::storage::MatchedEntry;
+    use serde_json::{Map, json};
+
+    fn storage() -> JsonStorage {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory).unwrap();
+        JsonStorage::new(directory)
+    }
+
+    fn record(queue: &str, players: Vec<Uuid>) -> MatchRecord {
+        MatchRecord {
+            id: Uuid::new_v4(),
+            queue: String::from(queue),
+            teams: vec![vec![MatchedEntry {
+                id: EntryId(Uuid::new_v4()),
+                players,
+                wait_seconds: 1.0,
+            }]],
+            game: json!({}),
+            region: None,
+            created_at: chrono::Utc::now(),
+            placements: None,
+        }
+    }
+
+    #[test]
+    fn test_queues_round_trip() {
+        let storage = storage();
+        assert!(storage.load_queues().unwrap().is_empty());
+
+        let mut entry = Entry::new(Uuid::new_v4(), vec![Uuid::new_v4()], Map::new());
+        entry.time_queued -= chrono::Duration::seconds(30);
+        let queue = QueueDefinition {
+            name: String::from("ranked"),
+            matchmaker: String::from("elo"),
+            settings: json!({"teamSize": 1}),
+            schema: None,
+            tick: Default::default(),
+            paused: true,
+            reject_joins: false,
+            entries: vec![entry],
+        };
+
+        storage.save_queues(std::slice::from_ref(&queue)).unwrap();
+        assert_eq!(storage.load_queues().unwrap(), vec![queue]);
+    }
+
+    #[test]
+    fn test_save_replaces_file() {
+        let storage = storage();
+        std::fs::write(storage.queues_path(), "[{\"name\": \"old\"}]").unwrap();
+
+        storage.save_queues(&[]).unwrap();
+        assert_eq!(std::fs::read_to_string(storage.queues_path()).unwrap(), "[]");
+        assert!(!storage.directory.join("queues.json.tmp").exists());
+    }
+
+    #[test]
+    fn test_invalid_queue_skipped() {
+        let storage = storage();
+        std::fs::write(
+            storage.queues_path(),
+            r#"[{"name": "broken"}, {"name": "casual", "matchmaker": "flexible", "settings": {}}]"#,
+        )
+        .unwrap();
+
+        let queues = storage.load_queues().unwrap();
+        assert_eq!(queues.len(), 1);
+        assert_eq!(queues[0].name, "casual");
+    }
+
+    #[test]
+    fn test_matches_filtered() {
+        let storage = storage();
+        let player = Uuid::new_v4();
+
+        for (queue, players) in [("ranked", vec![player]), ("casual", vec![player]), ("ranked", vec![Uuid::new_v4()])] {
+            storage.save_match(&record(queue, players)).unwrap();
+        }
+
+        let filter = MatchFilter {
+            queue: Some(String::from("ranked")),
+            player: Some(player),
+            ..Default::default()
+        };
+        assert_eq!(storage.get_matches(&filter).unwrap().len(), 1);
+        assert_eq!(storage.get_matches(&MatchFilter::default()).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_truncated_match_skipped() {
+        let storage = storage();
+        storage.save_match(&record("ranked", vec![Uuid::new_v4()])).unwrap();
+
+        let mut file = std::fs::OpenOptions::new().append(true).open(storage.matches_path()).unwrap();
+        file.write_all(br#"{"id":"#).unwrap();
+        storage.save_match(&record("ranked", vec![Uuid::new_v4()])).unwrap();
+
+        assert_eq!(storage.get_matches(&MatchFilter::default()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_save_result() {
+        let storage = storage();
+        let player = Uuid::new_v4();
+        let record = record("ranked", vec![player]);
+        storage.save_match(&record).unwrap();
+
+        let rating = PlayerRating::new(player, "ranked");
+        storage.save_result(record.id, &[1], std::slice::from_ref(&rating)).unwrap();
+        // A second report leaves the first one's ratings
+        let mut changed = rating.clone();
+        changed.elo += 10.0;
+        let err = storage.save_result(record.id, &[2], &[changed]).unwrap_err();
+        assert!(matches!(err, StorageError::AlreadyReported(id) if id == record.id));
+
+        assert_eq!(storage.get_match(record.id).unwrap().unwrap().placements, Some(vec![1]));
+        assert_eq!(storage.get_ratings("ranked", FIRST_SEASON, &[player]).unwrap(), vec![rating]);
+        assert!(storage.get_ratings("casual", FIRST_SEASON, &[player]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_profiles() {
+        let storage = storage();
+        let mut profiles: Vec<PlayerProfile> = (0..3).map(|_| PlayerProfile::new(Uuid::new_v4())).collect();
+        for profile in profiles.iter().rev() {
+            storage.save_profile(profile).unwrap();
+        }
+        profiles.sort_by_key(|profile| profile.player);
+
+        profiles[0].rating = Some(1200.0);
+        storage.save_profile(&profiles[0]).unwrap();
+        assert_eq!(storage.get_profiles(&[profiles[0].player]).unwrap(), vec![profiles[0].clone()]);
+        assert_eq!(storage.list_profiles(1, 10).unwrap(), profiles[1..]);
+
+        assert!(storage.delete_profile(profiles[0].player).unwrap());
+        assert!(!storage.delete_profile(profiles[0].player).unwrap());
+        assert_eq!(storage.list_profiles(0, 10).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,217 @@
+pub mod json;
+pub mod postgres;
+pub mod sqlite;
+
+use crate::entry::{Entry, EntryId};
+use crate::leaderboard::{RatingOrder, Season};
+use crate::profile::ProfileStore;
+use crate::queue::TickMode;
+use crate::rating::PlayerRating;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::info;
+use uuid::Uuid;
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("Failed to read or write storage file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse stored data: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Database error: {0}")]
+    Database(String),
+    #[error("Invalid storage configuration: {0}")]
+    Config(String),
+    #[error("The result of match {0} was already reported")]
+    AlreadyReported(Uuid),
+}
+
+impl From<diesel::result::Error> for StorageError {
+    fn from(err: diesel::result::Error) -> Self {
+        StorageError::Database(err.to_string())
+    }
+}
+
+fn is_default_tick_mode(tick_mode: &TickMode) -> bool {
+    *tick_mode == TickMode::default()
+}
+
+/// Everything needed to recreate a queue after a restart.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueDefinition {
+    pub name: String,
+    pub matchmaker: String,
+    pub settings: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Value>,
+    #[serde(default, skip_serializing_if = "is_default_tick_mode")]
+    pub tick: TickMode,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub paused: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reject_joins: bool,
+    /// Entries that were waiting in the queue
+    #[serde(default)]
+    pub entries: Vec<Entry>,
+}
+
+/// An entry as it was placed in a match.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchedEntry {
+    pub id: EntryId,
+    pub players: Vec<Uuid>,
+    pub wait_seconds: f64,
+}
+
+/// A completed match, kept so disputes can be audited afterwards.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchRecord {
+    pub id: Uuid,
+    pub queue: String,
+    pub teams: Vec<Vec<MatchedEntry>>,
+    /// Response of the game finder, `null` if no game was found
+    pub game: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// Place of each team once the game server reported the result, 1 being the best
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub placements: Option<Vec<u32>>,
+}
+
+impl MatchRecord {
+    /// Records a match formed now, out of `teams` waiting in `queue`.
+    pub fn new(queue: &str, teams: &[Vec<Entry>], game: Value, region: Option<String>) -> Self {
+        let created_at = Utc::now();
+        let teams = teams
+            .iter()
+            .map(|team| {
+                team.iter()
+                    .map(|entry| MatchedEntry {
+                        id: entry.id,
+                        players: entry.players.clone(),
+                        wait_seconds: (created_at - entry.time_queued).num_milliseconds() as f64 / 1000.0,
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            id: Uuid::new_v4(),
+            queue: String::from(queue),
+            teams,
+            game,
+            region,
+            created_at,
+            placements: None,
+        }
+    }
+
+    pub fn players(&self) -> impl Iterator<Item = &Uuid> {
+        self.teams.iter().flatten().flat_map(|entry| &entry.players)
+    }
+}
+
+/// Which matches to return, every field narrows the result.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchFilter {
+    pub queue: Option<String>,
+    pub player: Option<Uuid>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+impl MatchFilter {
+    pub fn matches(&self, record: &MatchRecord) -> bool {
+        self.queue.as_ref().is_none_or(|queue| *queue == record.queue)
+            && self.player.is_none_or(|player| record.players().any(|x| *x == player))
+            && self.from.is_none_or(|from| record.created_at >= from)
+            && self.to.is_none_or(|to| record.created_at <= to)
+    }
+}
+
+/// Where queues, their waiting entries, completed matches, ratings and player profiles are kept.
+pub trait Storage: ProfileStore {
+    fn load_queues(&self) -> Result<Vec<QueueDefinition>, StorageError>;
+
+    /// Replaces every stored queue and waiting entry with `queues`.
+    fn save_queues(&self, queues: &[QueueDefinition]) -> Result<(), StorageError>;
+
+    fn save_match(&self, record: &MatchRecord) -> Result<(), StorageError>;
+
+    /// Matches passing `filter`, newest first.
+    fn get_matches(&self, filter: &MatchFilter) -> Result<Vec<MatchRecord>, StorageError>;
+
+    fn get_match(&self, id: Uuid) -> Result<Option<MatchRecord>, StorageError>;
+
+    /// Stores the placements of a match together with the updated ratings of its players. Fails
+    /// with [`StorageError::AlreadyReported`] without changing any rating if the match already
+    /// has placements, also when another server reported it concurrently.
+    fn save_result(&self, id: Uuid, placements: &[u32], ratings: &[PlayerRating]) -> Result<(), StorageError>;
+
+    /// Stored ratings of `players` in `season` of `queue`, players without a reported match that
+    /// season are left out.
+    fn get_ratings(&self, queue: &str, season: u32, players: &[Uuid]) -> Result<Vec<PlayerRating>, StorageError>;
+
+    /// The season of `queue` new results are rated in, the first season if it was never reset.
+    fn current_season(&self, queue: &str) -> Result<Season, StorageError>;
+
+    /// Ends the current season of `queue` and returns the one following it.
+    fn start_season(&self, queue: &str) -> Result<Season, StorageError>;
+
+    /// Ratings in `season` of `queue` sorted by `order`, see [`RatingOrder::compare`].
+    fn get_leaderboard(
+        &self,
+        queue: &str,
+        season: u32,
+        order: RatingOrder,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<PlayerRating>, StorageError>;
+
+    /// Position of `player` in the leaderboard, starting at 1, and their rating.
+    fn get_rank(
+        &self,
+        queue: &str,
+        season: u32,
+        order: RatingOrder,
+        player: Uuid,
+    ) -> Result<Option<(usize, PlayerRating)>, StorageError>;
+}
+
+/// Creates the storage selected with the `STORAGE` environment variable:
+///
+/// - `json` (default): `queues.json`, `matches.jsonl`, `ratings.json`, `seasons.json` and
+///   `profiles.json` in the working directory
+/// - `sqlite`: the SQLite file at `SQLITE_PATH` (default: `matchmaker.db`)
+/// - `postgres`: the database at `DATABASE_URL`
+pub fn from_env() -> Result<Arc<dyn Storage>, StorageError> {
+    let backend = std::env::var("STORAGE").unwrap_or_else(|_| String::from("json"));
+
+    match backend.as_str() {
+        "json" => {
+            info!("Storing queues in queues.json");
+            Ok(Arc::new(json::JsonStorage::new(".")))
+        }
+        "sqlite" => {
+            let path = std::env::var("SQLITE_PATH").unwrap_or_else(|_| String::from("matchmaker.db"));
+            info!("Storing queues in {}", path);
+            Ok(Arc::new(sqlite::SqliteStorage::open(path)?))
+        }
+        "postgres" => {
+            let url = std::env::var("DATABASE_URL")
+                .map_err(|_| StorageError::Config(String::from("DATABASE_URL is not set")))?;
+            info!("Storing queues in PostgreSQL");
+            Ok(Arc::new(postgres::PostgresStorage::connect(&url)?))
+        }
+        other => Err(StorageError::Config(format!("Unknown storage backend: {}", other))),
+    }
+}
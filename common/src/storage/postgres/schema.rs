@@ -0,0 +1,68 @@
+diesel::table! {
+    entries (id) {
+        id -> Uuid,
+        queue -> Text,
+        players -> Array<Uuid>,
+        metadata -> Jsonb,
+        time_queued -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    matches (id) {
+        id -> Uuid,
+        queue -> Text,
+        teams -> Jsonb,
+        players -> Array<Uuid>,
+        game -> Jsonb,
+        region -> Nullable<Text>,
+        created_at -> Timestamptz,
+        placements -> Nullable<Array<Int4>>,
+    }
+}
+
+diesel::table! {
+    profiles (player) {
+        player -> Uuid,
+        profile -> Jsonb,
+    }
+}
+
+diesel::table! {
+    queues (name) {
+        name -> Text,
+        matchmaker -> Text,
+        settings -> Jsonb,
+        schema -> Nullable<Jsonb>,
+        tick -> Jsonb,
+        paused -> Bool,
+        reject_joins -> Bool,
+    }
+}
+
+diesel::table! {
+    ratings (queue, season, player) {
+        queue -> Text,
+        player -> Uuid,
+        elo -> Float8,
+        glicko_rating -> Float8,
+        glicko_rd -> Float8,
+        glicko_volatility -> Float8,
+        matches -> Int4,
+        wins -> Int4,
+        updated_at -> Timestamptz,
+        season -> Int4,
+    }
+}
+
+diesel::table! {
+    seasons (queue) {
+        queue -> Text,
+        season -> Int4,
+        started_at -> Timestamptz,
+    }
+}
+
+diesel::joinable!(entries -> queues (queue));
+
+diesel::allow_tables_to_appear_in_same_query!(entries, matches, profiles, queues, ratings, seasons);
@@ -0,0 +1,736 @@
+mod schema;
+
+use crate::algo::glicko2::Glicko2Rating;
+use crate::entry::{Entry, EntryId};
+use crate::leaderboard::{FIRST_SEASON, RatingOrder, Season};
+use crate::profile::{PlayerProfile, ProfileStore};
+use crate::rating::PlayerRating;
+use crate::storage::{MatchFilter, MatchRecord, QueueDefinition, Storage, StorageError};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+use diesel_migrations::{EmbeddedMigrations, MigrationHarness, embed_migrations};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use uuid::Uuid;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("../migrations/sqlite");
+
+/// Rows inserted per statement, SQLite allows at most 32766 parameters
+const INSERT_CHUNK_SIZE: usize = 1000;
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::queues)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+struct QueueRow {
+    name: String,
+    matchmaker: String,
+    settings: String,
+    schema: Option<String>,
+    tick: String,
+    paused: bool,
+    reject_joins: bool,
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::entries)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+struct EntryRow {
+    id: String,
+    queue: String,
+    players: String,
+    metadata: String,
+    time_queued: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::matches)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+struct MatchRow {
+    id: String,
+    queue: String,
+    teams: String,
+    game: String,
+    region: Option<String>,
+    created_at: NaiveDateTime,
+    /// JSON array
+    placements: Option<String>,
+}
+
+fn season_to_i32(season: u32) -> i32 {
+    i32::try_from(season).unwrap_or(i32::MAX)
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::ratings)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+struct RatingRow {
+    queue: String,
+    season: i32,
+    player: String,
+    elo: f64,
+    glicko_rating: f64,
+    glicko_rd: f64,
+    glicko_volatility: f64,
+    matches: i32,
+    wins: i32,
+    updated_at: NaiveDateTime,
+}
+
+impl From<&PlayerRating> for RatingRow {
+    fn from(rating: &PlayerRating) -> Self {
+        Self {
+            queue: rating.queue.clone(),
+            season: season_to_i32(rating.season),
+            player: rating.player.to_string(),
+            elo: rating.elo,
+            glicko_rating: rating.glicko.rating,
+            glicko_rd: rating.glicko.rd,
+            glicko_volatility: rating.glicko.volatility,
+            matches: i32::try_from(rating.matches).unwrap_or(i32::MAX),
+            wins: i32::try_from(rating.wins).unwrap_or(i32::MAX),
+            updated_at: rating.updated_at.naive_utc(),
+        }
+    }
+}
+
+impl TryFrom<RatingRow> for PlayerRating {
+    type Error = StorageError;
+
+    fn try_from(row: RatingRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            player: parse_uuid(&row.player)?,
+            queue: row.queue,
+            season: u32::try_from(row.season).unwrap_or(FIRST_SEASON),
+            elo: row.elo,
+            glicko: Glicko2Rating {
+                rating: row.glicko_rating,
+                rd: row.glicko_rd,
+                volatility: row.glicko_volatility,
+            },
+            matches: u32::try_from(row.matches).unwrap_or(0),
+            wins: u32::try_from(row.wins).unwrap_or(0),
+            updated_at: row.updated_at.and_utc(),
+        })
+    }
+}
+
+impl TryFrom<MatchRow> for MatchRecord {
+    type Error = StorageError;
+
+    fn try_from(row: MatchRow) -> Result<Self, Self::Error> {
+        Ok(MatchRecord {
+            id: parse_uuid(&row.id)?,
+            queue: row.queue,
+            teams: serde_json::from_str(&row.teams)?,
+            game: serde_json::from_str(&row.game)?,
+            region: row.region,
+            created_at: row.created_at.and_utc(),
+            placements: row.placements.as_deref().map(serde_json::from_str).transpose()?,
+        })
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = schema::match_players)]
+struct MatchPlayerRow {
+    match_id: String,
+    player: String,
+}
+
+fn parse_uuid(value: &str) -> Result<Uuid, StorageError> {
+    Uuid::parse_str(value).map_err(|err| StorageError::Database(format!("Invalid id {}: {}", value, err)))
+}
+
+fn current_season(connection: &mut SqliteConnection, queue: &str) -> Result<Season, StorageError> {
+    use schema::seasons;
+
+    let row: Option<(i32, NaiveDateTime)> = seasons::table
+        .select((seasons::season, seasons::started_at))
+        .find(queue)
+        .first(connection)
+        .optional()?;
+
+    Ok(match row {
+        Some((number, started_at)) => Season {
+            queue: String::from(queue),
+            number: u32::try_from(number).unwrap_or(FIRST_SEASON),
+            started_at: Some(started_at.and_utc()),
+        },
+        None => Season::first(queue),
+    })
+}
+
+/// Keeps queues, their waiting entries and completed matches in a SQLite file, for deployments
+/// running a single server. Pending migrations are run when opening the file.
+pub struct SqliteStorage {
+    connection: Mutex<SqliteConnection>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        let url = path.as_ref().to_string_lossy();
+        let mut connection =
+            SqliteConnection::establish(&url).map_err(|err| StorageError::Database(err.to_string()))?;
+        diesel::sql_query("PRAGMA foreign_keys = ON").execute(&mut connection)?;
+        diesel::sql_query("PRAGMA journal_mode = WAL").execute(&mut connection)?;
+        connection
+            .run_pending_migrations(MIGRATIONS)
+            .map_err(|err| StorageError::Database(format!("Failed to run migrations: {}", err)))?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn connection(&self) -> Result<MutexGuard<'_, SqliteConnection>, StorageError> {
+        self.connection
+            .lock()
+            .map_err(|_| StorageError::Database(String::from("Connection lock poisoned")))
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load_queues(&self) -> Result<Vec<QueueDefinition>, StorageError> {
+        use schema::{entries, queues};
+
+        let mut connection = self.connection()?;
+        let queue_rows: Vec<QueueRow> = queues::table
+            .select(QueueRow::as_select())
+            .order(queues::name)
+            .load(&mut *connection)?;
+        let entry_rows: Vec<EntryRow> = entries::table
+            .select(EntryRow::as_select())
+            .order(entries::time_queued)
+            .load(&mut *connection)?;
+
+        let mut queue_entries: HashMap<String, Vec<Entry>> = HashMap::new();
+        for row in entry_rows {
+            queue_entries.entry(row.queue).or_default().push(Entry {
+                id: EntryId(parse_uuid(&row.id)?),
+                players: serde_json::from_str(&row.players)?,
+                time_queued: row.time_queued.and_utc(),
+                metadata: serde_json::from_str(&row.metadata)?,
+            });
+        }
+
+        let mut definitions: Vec<QueueDefinition> = Vec::new();
+        for row in queue_rows {
+            definitions.push(QueueDefinition {
+                entries: queue_entries.remove(&row.name).unwrap_or_default(),
+                name: row.name,
+                matchmaker: row.matchmaker,
+                settings: serde_json::from_str(&row.settings)?,
+                schema: row.schema.as_deref().map(serde_json::from_str).transpose()?,
+                tick: serde_json::from_str(&row.tick)?,
+                paused: row.paused,
+                reject_joins: row.reject_joins,
+            });
+        }
+        Ok(definitions)
+    }
+
+    fn save_queues(&self, definitions: &[QueueDefinition]) -> Result<(), StorageError> {
+        use schema::{entries, queues};
+
+        let mut queue_rows: Vec<QueueRow> = Vec::new();
+        let mut entry_rows: Vec<EntryRow> = Vec::new();
+        for definition in definitions {
+            queue_rows.push(QueueRow {
+                name: definition.name.clone(),
+                matchmaker: definition.matchmaker.clone(),
+                settings: serde_json::to_string(&definition.settings)?,
+                schema: definition.schema.as_ref().map(serde_json::to_string).transpose()?,
+                tick: serde_json::to_string(&definition.tick)?,
+                paused: definition.paused,
+                reject_joins: definition.reject_joins,
+            });
+            for entry in &definition.entries {
+                entry_rows.push(EntryRow {
+                    id: entry.id.0.to_string(),
+                    queue: definition.name.clone(),
+                    players: serde_json::to_string(&entry.players)?,
+                    metadata: serde_json::to_string(&entry.metadata)?,
+                    time_queued: entry.time_queued.naive_utc(),
+                });
+            }
+        }
+
+        // Either every queue is written or the previous state is kept
+        let mut connection = self.connection()?;
+        connection.transaction::<_, StorageError, _>(|connection| {
+            diesel::delete(entries::table).execute(connection)?;
+            diesel::delete(queues::table).execute(connection)?;
+            for chunk in queue_rows.chunks(INSERT_CHUNK_SIZE) {
+                diesel::insert_into(queues::table).values(chunk).execute(connection)?;
+            }
+            for chunk in entry_rows.chunks(INSERT_CHUNK_SIZE) {
+                diesel::insert_into(entries::table).values(chunk).execute(connection)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn save_match(&self, record: &MatchRecord) -> Result<(), StorageError> {
+        use schema::{match_players, matches};
+
+        let row = MatchRow {
+            id: record.id.to_string(),
+            queue: record.queue.clone(),
+            teams: serde_json::to_string(&record.teams)?,
+            game: serde_json::to_string(&record.game)?,
+            region: record.region.clone(),
+            created_at: record.created_at.naive_utc(),
+            placements: record.placements.as_ref().map(serde_json::to_string).transpose()?,
+        };
+        let players: HashSet<&Uuid> = record.players().collect();
+        let player_rows: Vec<MatchPlayerRow> = players
+            .into_iter()
+            .map(|player| MatchPlayerRow {
+                match_id: row.id.clone(),
+                player: player.to_string(),
+            })
+            .collect();
+
+        let mut connection = self.connection()?;
+        connection.transaction::<_, StorageError, _>(|connection| {
+            diesel::insert_into(matches::table).values(&row).execute(connection)?;
+            for chunk in player_rows.chunks(INSERT_CHUNK_SIZE) {
+                diesel::insert_into(match_players::table).values(chunk).execute(connection)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn get_matches(&self, filter: &MatchFilter) -> Result<Vec<MatchRecord>, StorageError> {
+        use schema::{match_players, matches};
+
+        let mut query = matches::table
+            .select(MatchRow::as_select())
+            .order(matches::created_at.desc())
+            .into_boxed();
+        if let Some(queue) = &filter.queue {
+            query = query.filter(matches::queue.eq(queue.clone()));
+        }
+        if let Some(player) = filter.player {
+            let player_matches = match_players::table
+                .select(match_players::match_id)
+                .filter(match_players::player.eq(player.to_string()));
+            query = query.filter(matches::id.eq_any(player_matches));
+        }
+        if let Some(from) = filter.from {
+            query = query.filter(matches::created_at.ge(from.naive_utc()));
+        }
+        if let Some(to) = filter.to {
+            query = query.filter(matches::created_at.le(to.naive_utc()));
+        }
+        if let Some(limit) = filter.limit {
+            query = query.limit(i64::try_from(limit).unwrap_or(i64::MAX));
+        }
+
+        let mut connection = self.connection()?;
+        let rows: Vec<MatchRow> = query.load(&mut *connection)?;
+
+        rows.into_iter().map(MatchRecord::try_from).collect()
+    }
+
+    fn get_match(&self, id: Uuid) -> Result<Option<MatchRecord>, StorageError> {
+        use schema::matches;
+
+        let mut connection = self.connection()?;
+        let row: Option<MatchRow> = matches::table
+            .select(MatchRow::as_select())
+            .find(id.to_string())
+            .first(&mut *connection)
+            .optional()?;
+        row.map(MatchRecord::try_from).transpose()
+    }
+
+    fn save_result(&self, id: Uuid, placements: &[u32], ratings: &[PlayerRating]) -> Result<(), StorageError> {
+        use schema::{matches, ratings};
+
+        let placements = serde_json::to_string(placements)?;
+        let rows: Vec<RatingRow> = ratings.iter().map(RatingRow::from).collect();
+
+        let mut connection = self.connection()?;
+        connection.transaction::<_, StorageError, _>(|connection| {
+            // Only a match without placements is updated, so concurrent reports can't both apply
+            let updated = diesel::update(matches::table.find(id.to_string()).filter(matches::placements.is_null()))
+                .set(matches::placements.eq(Some(placements)))
+                .execute(connection)?;
+            if updated == 0 {
+                let exists: i64 = matches::table.find(id.to_string()).count().get_result(connection)?;
+                if exists > 0 {
+                    return Err(StorageError::AlreadyReported(id));
+                }
+                return Err(StorageError::Database(format!("Match {} not found", id)));
+            }
+
+            for row in &rows {
+                diesel::replace_into(ratings::table).values(row).execute(connection)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn get_ratings(&self, queue: &str, season: u32, players: &[Uuid]) -> Result<Vec<PlayerRating>, StorageError> {
+        use schema::ratings;
+
+        let players: Vec<String> = players.iter().map(Uuid::to_string).collect();
+        let mut connection = self.connection()?;
+        let rows: Vec<RatingRow> = ratings::table
+            .select(RatingRow::as_select())
+            .filter(ratings::queue.eq(queue))
+            .filter(ratings::season.eq(season_to_i32(season)))
+            .filter(ratings::player.eq_any(players))
+            .load(&mut *connection)?;
+        rows.into_iter().map(PlayerRating::try_from).collect()
+    }
+
+    fn current_season(&self, queue: &str) -> Result<Season, StorageError> {
+        let mut connection = self.connection()?;
+        current_season(&mut connection, queue)
+    }
+
+    fn start_season(&self, queue: &str) -> Result<Season, StorageError> {
+        use schema::seasons;
+
+        let mut connection = self.connection()?;
+        connection.transaction::<_, StorageError, _>(|connection| {
+            let now = Utc::now();
+            let current = current_season(connection, queue)?;
+            let season = Season {
+                queue: String::from(queue),
+                number: current.number + 1,
+                started_at: Some(now),
+            };
+
+            diesel::replace_into(seasons::table)
+                .values((
+                    seasons::queue.eq(queue),
+                    seasons::season.eq(season_to_i32(season.number)),
+                    seasons::started_at.eq(now.naive_utc()),
+                ))
+                .execute(connection)?;
+            Ok(season)
+        })
+    }
+
+    fn get_leaderboard(
+        &self,
+        queue: &str,
+        season: u32,
+        order: RatingOrder,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<PlayerRating>, StorageError> {
+        use schema::ratings;
+
+        let query = ratings::table
+            .select(RatingRow::as_select())
+            .filter(ratings::queue.eq(queue))
+            .filter(ratings::season.eq(season_to_i32(season)))
+            .offset(i64::try_from(offset).unwrap_or(i64::MAX))
+            .limit(i64::try_from(limit).unwrap_or(i64::MAX))
+            .into_boxed();
+        let query = match order {
+            RatingOrder::Elo => query.order((ratings::elo.desc(), ratings::player)),
+            RatingOrder::Glicko => query.order((ratings::glicko_rating.desc(), ratings::player)),
+        };
+
+        let mut connection = self.connection()?;
+        let rows: Vec<RatingRow> = query.load(&mut *connection)?;
+        rows.into_iter().map(PlayerRating::try_from).collect()
+    }
+
+    fn get_rank(
+        &self,
+        queue: &str,
+        season: u32,
+        order: RatingOrder,
+        player: Uuid,
+    ) -> Result<Option<(usize, PlayerRating)>, StorageError> {
+        use schema::ratings;
+
+        let (season, player) = (season_to_i32(season), player.to_string());
+        let mut connection = self.connection()?;
+        let row: Option<RatingRow> = ratings::table
+            .select(RatingRow::as_select())
+            .find((queue, season, &player))
+            .first(&mut *connection)
+            .optional()?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        // Players ahead have a higher rating, or the same rating and a lower id
+        let query = ratings::table
+            .filter(ratings::queue.eq(queue))
+            .filter(ratings::season.eq(season))
+            .into_boxed();
+        let query = match order {
+            RatingOrder::Elo => query.filter(
+                ratings::elo
+                    .gt(row.elo)
+                    .or(ratings::elo.eq(row.elo).and(ratings::player.lt(&player))),
+            ),
+            RatingOrder::Glicko => query.filter(
+                ratings::glicko_rating
+                    .gt(row.glicko_rating)
+                    .or(ratings::glicko_rating.eq(row.glicko_rating).and(ratings::player.lt(&player))),
+            ),
+        };
+        let ahead: i64 = query.count().get_result(&mut *connection)?;
+
+        Ok(Some((usize::try_from(ahead).unwrap_or(0) + 1, PlayerRating::try_from(row)?)))
+    }
+}
+
+impl ProfileStore for SqliteStorage {
+    fn get_profiles(&self, players: &[Uuid]) -> Result<Vec<PlayerProfile>, StorageError> {
+        use schema::profiles;
+
+        let players: Vec<String> = players.iter().map(Uuid::to_string).collect();
+        let mut connection = self.connection()?;
+        let rows: Vec<String> = profiles::table
+            .select(profiles::profile)
+            .filter(profiles::player.eq_any(players))
+            .load(&mut *connection)?;
+        Ok(rows.iter().map(|row| serde_json::from_str(row)).collect::<Result<_, _>>()?)
+    }
+
+    fn list_profiles(&self, offset: usize, limit: usize) -> Result<Vec<PlayerProfile>, StorageError> {
+        use schema::profiles;
+
+        let mut connection = self.connection()?;
+        let rows: Vec<String> = profiles::table
+            .select(profiles::profile)
+            .order(profiles::player)
+            .offset(i64::try_from(offset).unwrap_or(i64::MAX))
+            .limit(i64::try_from(limit).unwrap_or(i64::MAX))
+            .load(&mut *connection)?;
+        Ok(rows.iter().map(|row| serde_json::from_str(row)).collect::<Result<_, _>>()?)
+    }
+
+    fn save_profile(&self, profile: &PlayerProfile) -> Result<(), StorageError> {
+        use schema::profiles;
+
+        let value = serde_json::to_string(profile)?;
+        let mut connection = self.connection()?;
+        diesel::replace_into(profiles::table)
+            .values((profiles::player.eq(profile.player.to_string()), profiles::profile.eq(value)))
+            .execute(&mut *connection)?;
+        Ok(())
+    }
+
+    fn delete_profile(&self, player: Uuid) -> Result<bool, StorageError> {
+        use schema::profiles;
+
+        let mut connection = self.connection()?;
+        let deleted = diesel::delete(profiles::table.find(player.to_string())).execute(&mut *connection)?;
+        Ok(deleted > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::TickMode;
+    use crate::rating::MatchOutcome;
+    use crate::storage::MatchedEntry;
+    use chrono::{DateTime, Utc};
+    use serde_json::{Map, json};
+
+    fn storage() -> SqliteStorage {
+        SqliteStorage::open(std::env::temp_dir().join(format!("{}.db", Uuid::new_v4()))).unwrap()
+    }
+
+    fn record(queue: &str, players: Vec<Uuid>, created_at: DateTime<Utc>) -> MatchRecord {
+        MatchRecord {
+            id: Uuid::new_v4(),
+            queue: String::from(queue),
+            teams: vec![vec![MatchedEntry {
+                id: EntryId(Uuid::new_v4()),
+                players,
+                wait_seconds: 2.5,
+            }]],
+            game: json!({"host": "127.0.0.1"}),
+            region: None,
+            created_at,
+            placements: None,
+        }
+    }
+
+    #[test]
+    fn test_queues_round_trip() {
+        let storage = storage();
+        assert!(storage.load_queues().unwrap().is_empty());
+
+        let mut entry = Entry::new(Uuid::new_v4(), vec![Uuid::new_v4(), Uuid::new_v4()], Map::new());
+        entry.metadata.insert(String::from("elo"), json!(1200));
+        let queue = QueueDefinition {
+            name: String::from("ranked"),
+            matchmaker: String::from("elo"),
+            settings: json!({"teamSize": 1}),
+            schema: Some(json!({"required": ["elo"]})),
+            tick: TickMode::Event {
+                debounce_ms: 100,
+                max_interval_ms: 5000,
+            },
+            paused: true,
+            reject_joins: true,
+            entries: vec![entry],
+        };
+
+        storage.save_queues(std::slice::from_ref(&queue)).unwrap();
+        assert_eq!(storage.load_queues().unwrap(), vec![queue]);
+
+        storage.save_queues(&[]).unwrap();
+        assert!(storage.load_queues().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_failed_save_keeps_queues() {
+        let storage = storage();
+        let queue = QueueDefinition {
+            name: String::from("casual"),
+            matchmaker: String::from("flexible"),
+            settings: json!({}),
+            schema: None,
+            tick: TickMode::default(),
+            paused: false,
+            reject_joins: false,
+            entries: Vec::new(),
+        };
+        storage.save_queues(std::slice::from_ref(&queue)).unwrap();
+
+        // The duplicate name fails the insert, the delete before it is rolled back
+        let duplicate = [queue.clone(), queue.clone()];
+        assert!(storage.save_queues(&duplicate).is_err());
+        assert_eq!(storage.load_queues().unwrap(), vec![queue]);
+    }
+
+    #[test]
+    fn test_save_result() {
+        let storage = storage();
+        let player = Uuid::new_v4();
+        let record = record("ranked", vec![player], Utc::now());
+        storage.save_match(&record).unwrap();
+
+        let mut rating = PlayerRating::new(player, "ranked");
+        rating.updated_at = DateTime::from_timestamp_micros(rating.updated_at.timestamp_micros()).unwrap();
+        storage.save_result(record.id, &[1], std::slice::from_ref(&rating)).unwrap();
+        // A second report leaves the first one's ratings
+        let mut changed = rating.clone();
+        changed.elo += 10.0;
+        let err = storage.save_result(record.id, &[2], &[changed]).unwrap_err();
+        assert!(matches!(err, StorageError::AlreadyReported(id) if id == record.id));
+
+        assert_eq!(storage.get_match(record.id).unwrap().unwrap().placements, Some(vec![1]));
+        assert_eq!(storage.get_ratings("ranked", FIRST_SEASON, &[player]).unwrap(), vec![rating]);
+        assert!(storage.get_ratings("casual", FIRST_SEASON, &[player]).unwrap().is_empty());
+        assert!(storage.save_result(Uuid::new_v4(), &[1], &[]).is_err());
+    }
+
+    #[test]
+    fn test_matches_filtered() {
+        let storage = storage();
+        let player = Uuid::new_v4();
+        let now = Utc::now();
+        let old = now - chrono::Duration::hours(2);
+
+        let newest = record("ranked", vec![player], now);
+        storage.save_match(&newest).unwrap();
+        storage.save_match(&record("ranked", vec![player], old)).unwrap();
+        storage.save_match(&record("ranked", vec![Uuid::new_v4()], now)).unwrap();
+        storage.save_match(&record("casual", vec![player], now)).unwrap();
+
+        let filter = MatchFilter {
+            queue: Some(String::from("ranked")),
+            player: Some(player),
+            ..Default::default()
+        };
+        let matches = storage.get_matches(&filter).unwrap();
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0], newest);
+
+        let filter = MatchFilter {
+            queue: Some(String::from("ranked")),
+            player: Some(player),
+            from: Some(now - chrono::Duration::hours(1)),
+            ..Default::default()
+        };
+        assert_eq!(storage.get_matches(&filter).unwrap(), vec![newest]);
+
+        let filter = MatchFilter {
+            limit: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(storage.get_matches(&filter).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let storage = storage();
+        let mut profiles: Vec<PlayerProfile> = (0..3).map(|_| PlayerProfile::new(Uuid::new_v4())).collect();
+        for profile in &profiles {
+            storage.save_profile(profile).unwrap();
+        }
+        profiles.sort_by_key(|profile| profile.player);
+
+        profiles[0].roles.push(String::from("tank"));
+        storage.save_profile(&profiles[0]).unwrap();
+        assert_eq!(storage.get_profiles(&[profiles[0].player]).unwrap(), vec![profiles[0].clone()]);
+        assert_eq!(storage.list_profiles(1, 10).unwrap(), profiles[1..]);
+
+        assert!(storage.delete_profile(profiles[0].player).unwrap());
+        assert!(!storage.delete_profile(profiles[0].player).unwrap());
+        assert_eq!(storage.list_profiles(0, 10).unwrap().len(), 2);
+    }
+
+    /// Reports a 1v1 match won by `winner`, the way a game server would.
+    fn report(storage: &dyn Storage, queue: &str, winner: Uuid, loser: Uuid) {
+        let teams = [winner, loser].map(|player| vec![Entry::new(Uuid::new_v4(), vec![player], Map::new())]);
+        let record = MatchRecord::new(queue, &teams, json!({"address": "127.0.0.1:7777"}), None);
+        storage.save_match(&record).unwrap();
+        let outcome = MatchOutcome {
+            winner: Some(0),
+            placements: None,
+        };
+        crate::rating::report_result(storage, record.id, &outcome).unwrap();
+    }
+
+    #[test]
+    fn test_leaderboard() {
+        let storage = storage();
+        let queue = String::from("ranked");
+        let players: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+        report(&storage, &queue, players[0], players[1]);
+        report(&storage, &queue, players[2], players[3]);
+
+        let elo: Vec<f64> = storage
+            .get_leaderboard(&queue, FIRST_SEASON, RatingOrder::Elo, 0, 10)
+            .unwrap()
+            .iter()
+            .map(|rating| rating.elo)
+            .collect();
+        assert_eq!(elo, vec![1016.0, 1016.0, 984.0, 984.0]);
+        let glicko = storage.get_leaderboard(&queue, FIRST_SEASON, RatingOrder::Glicko, 2, 2).unwrap();
+        assert!(glicko.iter().all(|rating| rating.player == players[1] || rating.player == players[3]));
+
+        // Tied players are ordered by id
+        let tied = players[0].max(players[2]);
+        let (rank, _) = storage.get_rank(&queue, FIRST_SEASON, RatingOrder::Elo, tied).unwrap().unwrap();
+        assert_eq!(rank, 2);
+        let first = players[0].min(players[2]);
+        let (rank, _) = storage.get_rank(&queue, FIRST_SEASON, RatingOrder::Glicko, first).unwrap().unwrap();
+        assert_eq!(rank, 1);
+
+        assert_eq!(storage.current_season(&queue).unwrap(), Season::first(&queue));
+        assert_eq!(storage.start_season(&queue).unwrap().number, FIRST_SEASON + 1);
+        assert_eq!(storage.current_season(&queue).unwrap().number, FIRST_SEASON + 1);
+        assert!(storage.get_leaderboard(&queue, FIRST_SEASON + 1, RatingOrder::Elo, 0, 10).unwrap().is_empty());
+        assert!(storage.get_rank(&queue, FIRST_SEASON + 1, RatingOrder::Elo, tied).unwrap().is_none());
+    }
+}
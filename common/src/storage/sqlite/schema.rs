@@ -0,0 +1,75 @@
+diesel::table! {
+    entries (id) {
+        id -> Text,
+        queue -> Text,
+        players -> Text,
+        metadata -> Text,
+        time_queued -> Timestamp,
+    }
+}
+
+diesel::table! {
+    match_players (match_id, player) {
+        match_id -> Text,
+        player -> Text,
+    }
+}
+
+diesel::table! {
+    matches (id) {
+        id -> Text,
+        queue -> Text,
+        teams -> Text,
+        game -> Text,
+        region -> Nullable<Text>,
+        created_at -> Timestamp,
+        placements -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    profiles (player) {
+        player -> Text,
+        profile -> Text,
+    }
+}
+
+diesel::table! {
+    queues (name) {
+        name -> Text,
+        matchmaker -> Text,
+        settings -> Text,
+        schema -> Nullable<Text>,
+        tick -> Text,
+        paused -> Bool,
+        reject_joins -> Bool,
+    }
+}
+
+diesel::table! {
+    ratings (queue, season, player) {
+        queue -> Text,
+        season -> Integer,
+        player -> Text,
+        elo -> Double,
+        glicko_rating -> Double,
+        glicko_rd -> Double,
+        glicko_volatility -> Double,
+        matches -> Integer,
+        wins -> Integer,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    seasons (queue) {
+        queue -> Text,
+        season -> Integer,
+        started_at -> Timestamp,
+    }
+}
+
+diesel::joinable!(entries -> queues (queue));
+diesel::joinable!(match_players -> matches (match_id));
+
+diesel::allow_tables_to_appear_in_same_query!(entries, match_players, matches, profiles, queues, ratings, seasons);
@@ -0,0 +1,29 @@
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::Json;
+use serde_json::{json, Value};
+use subtle::ConstantTimeEq;
+
+/// Whether the request carries the token in `RESULT_TOKEN` as a bearer token, as game servers
+/// and other trusted services do. Every request is refused while the variable isn't set, so
+/// these endpoints are never open by default.
+pub fn is_trusted(headers: &HeaderMap) -> bool {
+    let Some(token) = std::env::var("RESULT_TOKEN").ok().filter(|token| !token.is_empty()) else {
+        return false;
+    };
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|value| bool::from(value.as_bytes().ct_eq(token.as_bytes())))
+}
+
+/// Refuses requests that aren't [trusted](is_trusted), `action` says what they tried.
+pub fn require_trusted(headers: &HeaderMap, action: &str) -> Result<(), (StatusCode, Json<Value>)> {
+    if is_trusted(headers) {
+        return Ok(());
+    }
+    Err((
+        StatusCode::UNAUTHORIZED,
+        Json(json!({"error": format!("Only trusted services can {}", action), "code": "UNAUTHORIZED"})),
+    ))
+}
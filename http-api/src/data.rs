@@ -1,4 +1,7 @@
 use common::entry::Entry;
+use common::queue::QueueError as EntryError;
+use common::queue_tracker::QueueTrackerError;
+use common::schema::FieldError;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use uuid::Uuid;
@@ -8,25 +11,62 @@ pub struct QueueData {
     name: String,
     entries: Vec<Entry>,
     matchmaker: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    schema: Option<Value>,
+    #[serde(default)]
+    paused: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct QueueError {
     error: String,
+    /// Stable, machine-readable error code, e.g. `QUEUE_NOT_FOUND`
+    code: &'static str,
+    /// Metadata fields that didn't match the queue's schema
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<FieldError>,
 }
 
 impl QueueError {
-    pub fn new(error: String) -> Self {
-        Self { error }
+    pub fn new(code: &'static str, error: String) -> Self {
+        Self {
+            error,
+            code,
+            fields: Vec::new(),
+        }
+    }
+}
+
+impl From<QueueTrackerError> for QueueError {
+    fn from(error: QueueTrackerError) -> Self {
+        let fields = match &error {
+            QueueTrackerError::Queue(EntryError::InvalidMetadata(err))
+            | QueueTrackerError::Evicted(EntryError::InvalidMetadata(err)) => err.errors.clone(),
+            _ => Vec::new(),
+        };
+
+        Self {
+            error: error.to_string(),
+            code: error.code(),
+            fields,
+        }
     }
 }
 
 impl QueueData {
-    pub fn new(name: String, entries: Vec<Entry>, matchmaker: Value) -> Self {
+    pub fn new(
+        name: String,
+        entries: Vec<Entry>,
+        matchmaker: Value,
+        schema: Option<Value>,
+        paused: bool,
+    ) -> Self {
         QueueData {
             name,
             entries,
             matchmaker,
+            schema,
+            paused,
         }
     }
 }
@@ -0,0 +1,199 @@
+use crate::auth::require_trusted;
+use crate::state::AppState;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use common::leaderboard::RatingOrder;
+use common::storage::{Storage, StorageError};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Players returned when the request doesn't set a limit
+const DEFAULT_LIMIT: usize = 100;
+
+#[derive(Deserialize)]
+pub struct LeaderboardParams {
+    season: Option<u32>,
+    order: Option<RatingOrder>,
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct RankParams {
+    season: Option<u32>,
+    order: Option<RatingOrder>,
+}
+
+/// The rating players of the queue `name` are ranked by. `order` takes precedence, otherwise it
+/// depends on the queue's matchmaker, and deleted queues whose ratings are still stored are
+/// ranked by Elo.
+async fn rating_order(app_state: &AppState, name: &str, order: Option<RatingOrder>) -> RatingOrder {
+    if let Some(order) = order {
+        return order;
+    }
+
+    let registry = app_state.queue_tracker.lock().await;
+    let Some(queue) = registry.get_queue(name).await else {
+        return RatingOrder::Elo;
+    };
+    drop(registry);
+
+    let queue = queue.lock().await;
+    RatingOrder::for_schema(&queue.matchmaker().metadata_schema())
+}
+
+/// Runs `f` against the storage on the blocking thread pool.
+async fn with_storage<T: Send + 'static>(
+    app_state: &AppState,
+    f: impl FnOnce(&dyn Storage) -> Result<T, StorageError> + Send + 'static,
+) -> Result<T, (StatusCode, Json<Value>)> {
+    let storage: Arc<dyn Storage> = app_state.storage.clone();
+    match tokio::task::spawn_blocking(move || f(storage.as_ref())).await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(err)) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string(), "code": "STORAGE_ERROR"})),
+        )),
+        Err(err) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string(), "code": "STORAGE_ERROR"})),
+        )),
+    }
+}
+
+/// Lists the players of a queue by rating, highest first. Queues whose matchmaker uses
+/// Glicko-2 are ranked by Glicko-2 rating, every other queue by Elo. Deleted queues can still
+/// be listed.
+///
+/// **Request:**
+/// - Method: `GET`
+/// - Path: `/queue/{name}/leaderboard`
+/// - Query parameters, all optional:
+///   - `season` (u32): Season to list (default: the current season).
+///   - `order` (`elo` | `glicko`): Rating to rank by (default: the queue's, or `elo` for deleted queues).
+///   - `offset` (usize): Number of players to skip (default: 0).
+///   - `limit` (usize): Maximum number of players (default: 100).
+///
+/// **Response:**
+/// - `200 OK`: The leaderboard page.
+///   - Body: `{ "queue", "season", "order": "elo" | "glicko", "entries": [{ "rank", "player", "elo", "glicko", ... }] }`
+/// - `500 Internal Server Error`: `{ "error": "...", "code": "STORAGE_ERROR" }`
+pub async fn get_leaderboard_route(
+    app_state: State<AppState>,
+    Path(name): Path<String>,
+    Query(params): Query<LeaderboardParams>,
+) -> (StatusCode, Json<Value>) {
+    let order = rating_order(&app_state, &name, params.order).await;
+
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    let leaderboard = match with_storage(&app_state, move |storage| {
+        common::leaderboard::get_leaderboard(storage, &name, params.season, order, params.offset, limit)
+    })
+    .await
+    {
+        Ok(leaderboard) => leaderboard,
+        Err(err) => return err,
+    };
+
+    match serde_json::to_value(&leaderboard) {
+        Ok(json) => (StatusCode::OK, Json(json)),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string()})),
+        ),
+    }
+}
+
+/// Returns the rank and rating of a player on the leaderboard of a queue.
+///
+/// **Request:**
+/// - Method: `GET`
+/// - Path: `/queue/{name}/leaderboard/{player}`
+/// - Query parameters, all optional:
+///   - `season` (u32): Season to look in (default: the current season).
+///   - `order` (`elo` | `glicko`): Rating to rank by (default: the queue's, or `elo` for deleted queues).
+///
+/// **Response:**
+/// - `200 OK`: `{ "rank", "player", "elo", "glicko", ... }`
+/// - `404 Not Found`: The player has no reported match that season.
+///   - Body: `{ "error": "...", "code": "PLAYER_NOT_RANKED" }`
+/// - `500 Internal Server Error`: `{ "error": "...", "code": "STORAGE_ERROR" }`
+pub async fn get_rank_route(
+    app_state: State<AppState>,
+    Path((name, player)): Path<(String, Uuid)>,
+    Query(params): Query<RankParams>,
+) -> (StatusCode, Json<Value>) {
+    let order = rating_order(&app_state, &name, params.order).await;
+
+    let queue = name.clone();
+    let entry = match with_storage(&app_state, move |storage| {
+        common::leaderboard::get_rank(storage, &queue, params.season, order, player)
+    })
+    .await
+    {
+        Ok(entry) => entry,
+        Err(err) => return err,
+    };
+    let Some(entry) = entry else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "error": format!("Player {} has no rating in queue {}", player, name),
+                "code": "PLAYER_NOT_RANKED",
+            })),
+        );
+    };
+
+    match serde_json::to_value(&entry) {
+        Ok(json) => (StatusCode::OK, Json(json)),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string()})),
+        ),
+    }
+}
+
+/// Starts a new season of a queue. Players start over at the default rating, the previous
+/// seasons' leaderboards can still be read with `season`. The ratings of a deleted queue are
+/// kept, so its seasons can still be reset. Only trusted services may call it, with
+/// `Authorization: Bearer <RESULT_TOKEN>`.
+///
+/// **Request:**
+/// - Method: `POST`
+/// - Path: `/queue/{name}/leaderboard/reset`
+///
+/// **Response:**
+/// - `200 OK`: The new season.
+///   - Body: `{ "queue", "number", "startedAt" }`
+/// - `401 Unauthorized`: `{ "error": "...", "code": "UNAUTHORIZED" }`
+/// - `500 Internal Server Error`: `{ "error": "...", "code": "STORAGE_ERROR" }`
+pub async fn reset_season_route(
+    app_state: State<AppState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<Value>) {
+    if let Err(err) = require_trusted(&headers, "reset seasons") {
+        return err;
+    }
+
+    let season = match with_storage(&app_state, move |storage| {
+        common::leaderboard::start_season(storage, &name)
+    })
+    .await
+    {
+        Ok(season) => season,
+        Err(err) => return err,
+    };
+
+    match serde_json::to_value(&season) {
+        Ok(json) => (StatusCode::OK, Json(json)),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string()})),
+        ),
+    }
+}
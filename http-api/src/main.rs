@@ -1,11 +1,15 @@
+mod auth;
 mod data;
+mod leaderboard_routes;
+mod match_routes;
+mod player_routes;
 mod queue_routes;
 mod socket;
 mod state;
 
 use crate::state::AppState;
 use axum::Router;
-use axum::routing::{any, get, post};
+use axum::routing::{any, get, post, put};
 use common::gamefinder::GameFinder;
 use common::queue_tracker::QueueTracker;
 use std::error::Error;
@@ -38,12 +42,18 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     info!("Loading config...");
     let game_finder = GameFinder::new();
+    let storage = common::storage::from_env()?;
+    let profiles = common::profile::from_env(storage.clone())?;
 
     info!("Initializing queue tracker...");
-    let queue_tracker = QueueTracker::from_file(game_finder).await;
+    let queue_tracker = QueueTracker::load(game_finder, storage.clone(), profiles.clone()).await;
     let queue_tracker_clone = queue_tracker.clone();
 
-    let state = AppState { queue_tracker };
+    let state = AppState {
+        queue_tracker,
+        storage,
+        profiles,
+    };
 
     info!("Loaded all queues...");
 
@@ -52,8 +62,43 @@ async fn main() -> Result<(), Box<dyn Error>> {
             "/api/v1/queue",
             post(queue_routes::create_queue_route).get(queue_routes::get_queues_route),
         )
-        .route("/api/v1/queue/{name}", get(queue_routes::get_queue))
+        .route(
+            "/api/v1/queue/{name}",
+            get(queue_routes::get_queue)
+                .patch(queue_routes::update_queue_route)
+                .delete(queue_routes::delete_queue_route),
+        )
         .route("/api/v1/queue/{name}/join", any(socket::ws_upgrade))
+        .route("/api/v1/queue/{name}/pause", post(queue_routes::pause_queue_route))
+        .route("/api/v1/queue/{name}/resume", post(queue_routes::resume_queue_route))
+        .route(
+            "/api/v1/queue/{name}/explain/{entry_id}",
+            get(queue_routes::explain_entry),
+        )
+        .route(
+            "/api/v1/queue/{name}/leaderboard",
+            get(leaderboard_routes::get_leaderboard_route),
+        )
+        .route(
+            "/api/v1/queue/{name}/leaderboard/reset",
+            post(leaderboard_routes::reset_season_route),
+        )
+        .route(
+            "/api/v1/queue/{name}/leaderboard/{player}",
+            get(leaderboard_routes::get_rank_route),
+        )
+        .route("/api/v1/matches", get(match_routes::get_matches_route))
+        .route(
+            "/api/v1/matches/{id}/result",
+            post(match_routes::report_result_route),
+        )
+        .route("/api/v1/players", get(player_routes::get_profiles_route))
+        .route(
+            "/api/v1/players/{id}",
+            put(player_routes::put_profile_route)
+                .get(player_routes::get_profile_route)
+                .delete(player_routes::delete_profile_route),
+        )
         .layer(TraceLayer::new_for_http())
         .with_state(state);
 
@@ -68,30 +113,25 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
         tokio::select! {
             _ = sigterm.recv() => {
-                info!("SIGTERM received, waiting for all queues to become empty...");
+                info!("SIGTERM received, saving waiting entries...");
             }
             _ = sigint.recv() => {
-                info!("SIGINT received, waiting for all queues to become empty...");
+                info!("SIGINT received, saving waiting entries...");
             }
         }
 
-        loop {
-            let tracker = queue_tracker_clone.lock().await;
-            let all_empty = { tracker.all_queues_empty().await };
-            if all_empty {
-                info!("All queues are empty. Proceeding with shutdown.");
-                break;
-            }
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-
-            tracker.save_to_file().await;
-        }
+        // Waiting entries are restored on startup, players keep their place by reconnecting.
+        // Locking first stops the queues from matching any of them after they're saved.
+        let mut tracker = queue_tracker_clone.lock().await;
+        tracker.lock().await;
+        tracker.save().await;
+        info!("Saved all queues. Proceeding with shutdown.");
     };
 
     axum::serve(listener, app)
         .with_graceful_shutdown(shutdown_signal)
         .await
-        .map_err(|x| format!("Failed to service http api: {}", x.to_string()))?;
+        .map_err(|x| format!("Failed to service http api: {}", x))?;
 
     Ok(())
 }
@@ -0,0 +1,123 @@
+use crate::auth::require_trusted;
+use crate::state::AppState;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use common::rating::{MatchOutcome, ReportError};
+use common::storage::MatchFilter;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+/// Matches returned when the request doesn't set a limit
+const DEFAULT_LIMIT: usize = 100;
+
+/// Lists completed matches, newest first.
+///
+/// **Request:**
+/// - Method: `GET`
+/// - Path: `/matches`
+/// - Query parameters, all optional:
+///   - `queue` (String): Only matches of this queue.
+///   - `player` (Uuid): Only matches this player was part of.
+///   - `from` / `to` (RFC 3339 timestamp): Only matches formed in this time range.
+///   - `limit` (usize): Maximum number of matches (default: 100).
+///
+/// **Response:**
+/// - `200 OK`: Array of match records with the teams, each entry's wait time and the game
+///   finder response.
+/// - `500 Internal Server Error`: The match history couldn't be read.
+///   - Body: `{ "error": "...", "code": "STORAGE_ERROR" }`
+pub async fn get_matches_route(
+    app_state: State<AppState>,
+    Query(mut filter): Query<MatchFilter>,
+) -> (StatusCode, Json<Value>) {
+    filter.limit = Some(filter.limit.unwrap_or(DEFAULT_LIMIT));
+
+    let storage = app_state.storage.clone();
+    let matches = match tokio::task::spawn_blocking(move || storage.get_matches(&filter)).await {
+        Ok(Ok(matches)) => matches,
+        Ok(Err(err)) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": err.to_string(), "code": "STORAGE_ERROR"})),
+            );
+        }
+        Err(err) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": err.to_string(), "code": "STORAGE_ERROR"})),
+            );
+        }
+    };
+
+    match serde_json::to_value(&matches) {
+        Ok(json) => (StatusCode::OK, Json(json)),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string()})),
+        ),
+    }
+}
+
+/// Reports the outcome of a finished match and updates the rating of every player in it. Only
+/// game servers should call it, with `Authorization: Bearer <RESULT_TOKEN>`. Reports are
+/// refused while the `RESULT_TOKEN` environment variable isn't set.
+///
+/// **Request:**
+/// - Method: `POST`
+/// - Path: `/matches/{id}/result`
+/// - Body: JSON object with either field:
+///   - `winner` (usize): Index of the winning team, the other teams share second place.
+///   - `placements` (Vec<u32>): Place of each team, 1 being the best. Teams with the same
+///     place drew.
+///
+/// **Response:**
+/// - `200 OK`: The updated ratings of every player in the match.
+/// - `400 Bad Request`: The outcome doesn't fit the match.
+///   - Body: `{ "error": "...", "code": "INVALID_RESULT" }`
+/// - `401 Unauthorized`: `{ "error": "...", "code": "UNAUTHORIZED" }`
+/// - `404 Not Found`: `{ "error": "...", "code": "MATCH_NOT_FOUND" }`
+/// - `409 Conflict`: `{ "error": "...", "code": "RESULT_ALREADY_REPORTED" }`
+pub async fn report_result_route(
+    app_state: State<AppState>,
+    Path(match_id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(outcome): Json<MatchOutcome>,
+) -> (StatusCode, Json<Value>) {
+    if let Err(err) = require_trusted(&headers, "report results") {
+        return err;
+    }
+
+    let storage = app_state.storage.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        common::rating::report_result(storage.as_ref(), match_id, &outcome)
+    })
+    .await;
+
+    let ratings = match result {
+        Ok(Ok(ratings)) => ratings,
+        Ok(Err(err)) => {
+            let status = match err {
+                ReportError::MatchNotFound(_) => StatusCode::NOT_FOUND,
+                ReportError::AlreadyReported(_) => StatusCode::CONFLICT,
+                ReportError::InvalidResult(_) => StatusCode::BAD_REQUEST,
+                ReportError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            return (status, Json(json!({"error": err.to_string(), "code": err.code()})));
+        }
+        Err(err) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": err.to_string(), "code": "STORAGE_ERROR"})),
+            );
+        }
+    };
+
+    match serde_json::to_value(&ratings) {
+        Ok(json) => (StatusCode::OK, Json(json)),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string()})),
+        ),
+    }
+}
@@ -0,0 +1,178 @@
+use crate::auth::require_trusted;
+use crate::state::AppState;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use common::profile::{PlayerProfile, ProfileStore};
+use common::storage::StorageError;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Profiles returned when the request doesn't set a limit
+const DEFAULT_LIMIT: usize = 100;
+
+#[derive(Deserialize)]
+pub struct ListParams {
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+/// Runs `f` against the profile store on the blocking thread pool.
+async fn with_profiles<T: Send + 'static>(
+    app_state: &AppState,
+    f: impl FnOnce(&dyn ProfileStore) -> Result<T, StorageError> + Send + 'static,
+) -> Result<T, (StatusCode, Json<Value>)> {
+    let profiles: Arc<dyn ProfileStore> = app_state.profiles.clone();
+    match tokio::task::spawn_blocking(move || f(profiles.as_ref())).await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(err)) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string(), "code": "STORAGE_ERROR"})),
+        )),
+        Err(err) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string(), "code": "STORAGE_ERROR"})),
+        )),
+    }
+}
+
+fn profile_not_found(player: Uuid) -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({"error": format!("Profile of player {} not found", player), "code": "PROFILE_NOT_FOUND"})),
+    )
+}
+
+/// Lists player profiles ordered by player id.
+///
+/// **Request:**
+/// - Method: `GET`
+/// - Path: `/players`
+/// - Query parameters, all optional:
+///   - `offset` (usize): Number of profiles to skip (default: 0).
+///   - `limit` (usize): Maximum number of profiles (default: 100).
+///
+/// **Response:**
+/// - `200 OK`: Array of player profiles.
+/// - `500 Internal Server Error`: The profiles couldn't be read.
+///   - Body: `{ "error": "...", "code": "STORAGE_ERROR" }`
+pub async fn get_profiles_route(
+    app_state: State<AppState>,
+    Query(params): Query<ListParams>,
+) -> (StatusCode, Json<Value>) {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    let profiles = match with_profiles(&app_state, move |store| store.list_profiles(params.offset, limit)).await {
+        Ok(profiles) => profiles,
+        Err(err) => return err,
+    };
+
+    match serde_json::to_value(&profiles) {
+        Ok(json) => (StatusCode::OK, Json(json)),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string()})),
+        ),
+    }
+}
+
+/// Returns the profile of a player.
+///
+/// **Request:**
+/// - Method: `GET`
+/// - Path: `/players/{id}`
+///
+/// **Response:**
+/// - `200 OK`: The player profile.
+/// - `404 Not Found`: `{ "error": "...", "code": "PROFILE_NOT_FOUND" }`
+pub async fn get_profile_route(
+    app_state: State<AppState>,
+    Path(player): Path<Uuid>,
+) -> (StatusCode, Json<Value>) {
+    let profile = match with_profiles(&app_state, move |store| store.get_profiles(&[player])).await {
+        Ok(profiles) => profiles.into_iter().next(),
+        Err(err) => return err,
+    };
+    let Some(profile) = profile else {
+        return profile_not_found(player);
+    };
+
+    match serde_json::to_value(&profile) {
+        Ok(json) => (StatusCode::OK, Json(json)),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string()})),
+        ),
+    }
+}
+
+/// Creates or replaces the profile of a player. Only trusted services may call it, with
+/// `Authorization: Bearer <RESULT_TOKEN>`.
+///
+/// **Request:**
+/// - Method: `PUT`
+/// - Path: `/players/{id}`
+/// - Body: JSON object with optional fields:
+///   - `rating` (f64): Merged into entries as `elo`.
+///   - `region` (String): Merged into entries as `region`, the only region the regional matchmaker plays them in.
+///   - `roles` (Vec<String>)
+///   - `flags` (Vec<String>)
+///   - `attributes` (Object): Any other metadata, merged under its own key.
+///
+/// **Response:**
+/// - `200 OK`: The saved profile.
+/// - `401 Unauthorized`: `{ "error": "...", "code": "UNAUTHORIZED" }`
+/// - `500 Internal Server Error`: `{ "error": "...", "code": "STORAGE_ERROR" }`
+pub async fn put_profile_route(
+    app_state: State<AppState>,
+    Path(player): Path<Uuid>,
+    headers: HeaderMap,
+    Json(mut profile): Json<PlayerProfile>,
+) -> (StatusCode, Json<Value>) {
+    if let Err(err) = require_trusted(&headers, "change profiles") {
+        return err;
+    }
+    profile.player = player;
+    profile.updated_at = chrono::Utc::now();
+
+    let saved = profile.clone();
+    if let Err(err) = with_profiles(&app_state, move |store| store.save_profile(&saved)).await {
+        return err;
+    }
+
+    match serde_json::to_value(&profile) {
+        Ok(json) => (StatusCode::OK, Json(json)),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string()})),
+        ),
+    }
+}
+
+/// Deletes the profile of a player, entries they join with afterwards keep their own metadata.
+/// Only trusted services may call it, with `Authorization: Bearer <RESULT_TOKEN>`.
+///
+/// **Request:**
+/// - Method: `DELETE`
+/// - Path: `/players/{id}`
+///
+/// **Response:**
+/// - `200 OK`: `{ "status": "Profile deleted" }`
+/// - `401 Unauthorized`: `{ "error": "...", "code": "UNAUTHORIZED" }`
+/// - `404 Not Found`: `{ "error": "...", "code": "PROFILE_NOT_FOUND" }`
+pub async fn delete_profile_route(
+    app_state: State<AppState>,
+    Path(player): Path<Uuid>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<Value>) {
+    if let Err(err) = require_trusted(&headers, "delete profiles") {
+        return err;
+    }
+    match with_profiles(&app_state, move |store| store.delete_profile(player)).await {
+        Ok(true) => (StatusCode::OK, Json(json!({"status": "Profile deleted"}))),
+        Ok(false) => profile_not_found(player),
+        Err(err) => err,
+    }
+}
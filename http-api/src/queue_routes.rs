@@ -1,13 +1,15 @@
 use crate::data::QueueData;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::Json;
-use common::queue::Queue;
-use common::queue_tracker::QueueTracker;
+use common::entry::EntryId;
+use common::queue::{Queue, TickMode};
+use common::queue_tracker::{QueueTracker, QueueTrackerError};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use uuid::Uuid;
 use crate::state::AppState;
 
 #[derive(Serialize, Deserialize)]
@@ -15,6 +17,10 @@ pub struct CreateQueueRequest {
     name: String,
     matchmaker: String,
     settings: Value,
+    #[serde(default)]
+    schema: Option<Value>,
+    #[serde(default)]
+    tick: TickMode,
 }
 
 /// Creates a new queue.
@@ -26,6 +32,10 @@ pub struct CreateQueueRequest {
 ///   - `name` (String): Name of the queue.
 ///   - `matchmaker` (String): Matchmaker type.
 ///   - `settings` (serde_json::Value): Matchmaker settings.
+///   - `schema` (serde_json::Value, optional): JSON Schema entry metadata must match.
+///   - `tick` (TickMode, optional): When the matchmaker runs, either
+///     `{ "mode": "interval", "intervalMs": 1000 }` (default) or
+///     `{ "mode": "event", "debounceMs": 200, "maxIntervalMs": 5000 }`.
 /// - Example:
 ///   {
 ///     "name": "queue1",
@@ -37,7 +47,7 @@ pub struct CreateQueueRequest {
 /// - `201 Created`: Queue created successfully.
 ///   - Body: `{ "status": "Queue created successfully" }`
 /// - `400 Bad Request`: Error creating queue.
-///   - Body: Error message string.
+///   - Body: `{ "error": "...", "code": "QUEUE_ALREADY_EXISTS" }`
 #[axum::debug_handler]
 pub async fn create_queue_route(
     app_state: State<AppState>,
@@ -49,6 +59,8 @@ pub async fn create_queue_route(
         request.name.clone(),
         request.matchmaker.clone(),
         request.settings.clone(),
+        request.schema.clone(),
+        request.tick.clone(),
         true,
     )
     .await
@@ -57,7 +69,187 @@ pub async fn create_queue_route(
             StatusCode::CREATED,
             Json(json!({"status": "Queue created successfully"})),
         ),
-        Err(e) => (StatusCode::BAD_REQUEST, Json(Value::String(e.to_string()))),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": e.to_string(), "code": e.code()})),
+        ),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpdateQueueRequest {
+    #[serde(default)]
+    settings: Option<Value>,
+    #[serde(default)]
+    tick: Option<TickMode>,
+}
+
+/// Changes the matchmaker settings or tick mode of a queue, keeping its waiting entries and
+/// their time queued.
+///
+/// **Request:**
+/// - Method: `PATCH`
+/// - Path: `/queues/{name}`
+/// - Path parameter: `name` (String): Name of the queue.
+/// - Body: JSON object with fields:
+///   - `settings` (serde_json::Value, optional): New matchmaker settings, the matchmaker type
+///     can't change.
+///   - `tick` (TickMode, optional): New tick mode, used from the queue's next tick.
+///
+/// **Response:**
+/// - `200 OK`: Queue updated. Entries that don't fit the new settings were removed and
+///   received an `ENTRY_EVICTED` error.
+///   - Body: `{ "status": "Queue updated", "evicted": [{ "entry", "players", "error", "code" }] }`
+/// - `400 Bad Request`: Invalid settings, the queue is unchanged.
+///   - Body: `{ "error": "...", "code": "INVALID_SETTINGS" }`
+/// - `404 Not Found`: Queue not found.
+///   - Body: `{ "error": "...", "code": "QUEUE_NOT_FOUND" }`
+#[axum::debug_handler]
+pub async fn update_queue_route(
+    app_state: State<AppState>,
+    Path(name): Path<String>,
+    request: Json<UpdateQueueRequest>,
+) -> (StatusCode, Json<Value>) {
+    let mut queue_tracker = app_state.queue_tracker.lock().await;
+
+    match queue_tracker
+        .update(&name, request.settings.clone(), request.tick.clone(), true)
+        .await
+    {
+        Ok(evicted) => {
+            let evicted: Vec<Value> = evicted
+                .iter()
+                .map(|(entry, err)| {
+                    json!({
+                        "entry": entry.id,
+                        "players": entry.players,
+                        "error": err.to_string(),
+                        "code": err.code(),
+                    })
+                })
+                .collect();
+            (
+                StatusCode::OK,
+                Json(json!({"status": "Queue updated", "evicted": evicted})),
+            )
+        }
+        Err(e @ QueueTrackerError::QueueNotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": e.to_string(), "code": e.code()})),
+        ),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": e.to_string(), "code": e.code()})),
+        ),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PauseQueueParams {
+    #[serde(default)]
+    reject_joins: bool,
+}
+
+/// Pauses a queue, it keeps its waiting entries but forms no matches until resumed.
+///
+/// **Request:**
+/// - Method: `POST`
+/// - Path: `/queues/{name}/pause`
+/// - Path parameter: `name` (String): Name of the queue.
+/// - Query parameter: `rejectJoins` (bool, optional): Refuse new entries with a `QUEUE_PAUSED`
+///   error while paused.
+///
+/// **Response:**
+/// - `200 OK`: Queue paused.
+///   - Body: `{ "status": "Queue paused" }`
+/// - `404 Not Found`: Queue not found.
+///   - Body: `{ "error": "...", "code": "QUEUE_NOT_FOUND" }`
+#[axum::debug_handler]
+pub async fn pause_queue_route(
+    app_state: State<AppState>,
+    Path(name): Path<String>,
+    Query(params): Query<PauseQueueParams>,
+) -> (StatusCode, Json<Value>) {
+    let mut queue_tracker = app_state.queue_tracker.lock().await;
+
+    match queue_tracker.pause(&name, params.reject_joins, true).await {
+        Ok(_) => (StatusCode::OK, Json(json!({"status": "Queue paused"}))),
+        Err(e) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": e.to_string(), "code": e.code()})),
+        ),
+    }
+}
+
+/// Resumes a paused queue.
+///
+/// **Request:**
+/// - Method: `POST`
+/// - Path: `/queues/{name}/resume`
+/// - Path parameter: `name` (String): Name of the queue.
+///
+/// **Response:**
+/// - `200 OK`: Queue resumed.
+///   - Body: `{ "status": "Queue resumed" }`
+/// - `404 Not Found`: Queue not found.
+///   - Body: `{ "error": "...", "code": "QUEUE_NOT_FOUND" }`
+#[axum::debug_handler]
+pub async fn resume_queue_route(
+    app_state: State<AppState>,
+    Path(name): Path<String>,
+) -> (StatusCode, Json<Value>) {
+    let mut queue_tracker = app_state.queue_tracker.lock().await;
+
+    match queue_tracker.resume(&name, true).await {
+        Ok(_) => (StatusCode::OK, Json(json!({"status": "Queue resumed"}))),
+        Err(e) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": e.to_string(), "code": e.code()})),
+        ),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeleteQueueParams {
+    #[serde(default)]
+    drain: bool,
+}
+
+/// Deletes a queue.
+///
+/// **Request:**
+/// - Method: `DELETE`
+/// - Path: `/queues/{name}`
+/// - Path parameter: `name` (String): Name of the queue.
+/// - Query parameter: `drain` (bool, optional): Stop accepting entries and delete the queue
+///   once every waiting entry has been matched, instead of closing it right away.
+///
+/// **Response:**
+/// - `200 OK`: Queue deleted, waiting entries received a `QUEUE_CLOSED` error.
+///   - Body: `{ "status": "Queue deleted" }`
+/// - `202 Accepted`: Queue is draining.
+///   - Body: `{ "status": "Queue draining" }`
+/// - `404 Not Found`: Queue not found.
+///   - Body: `{ "error": "...", "code": "QUEUE_NOT_FOUND" }`
+#[axum::debug_handler]
+pub async fn delete_queue_route(
+    app_state: State<AppState>,
+    Path(name): Path<String>,
+    Query(params): Query<DeleteQueueParams>,
+) -> (StatusCode, Json<Value>) {
+    let mut queue_tracker = app_state.queue_tracker.lock().await;
+
+    match queue_tracker.delete(&name, params.drain, true).await {
+        Ok(_) if queue_tracker.get_queue(&name).await.is_some() => (
+            StatusCode::ACCEPTED,
+            Json(json!({"status": "Queue draining"})),
+        ),
+        Ok(_) => (StatusCode::OK, Json(json!({"status": "Queue deleted"}))),
+        Err(e) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": e.to_string(), "code": e.code()})),
+        ),
     }
 }
 
@@ -116,7 +308,7 @@ pub async fn get_queue(
     let Some(queue) = queue else {
         return (
             StatusCode::NOT_FOUND,
-            Json(json!({"error": format!("Queue {} does not exist", name)})),
+            Json(json!({"error": format!("Queue {} does not exist", name), "code": "QUEUE_NOT_FOUND"})),
         );
     };
 
@@ -131,7 +323,7 @@ pub async fn get_queue(
     };
 
     let matchmaker = json!({
-        "type": matchmaker.get_type_name(),
+        "type": queue.matchmaker_type(),
         "settings": matchmaker_settings
     });
 
@@ -139,6 +331,8 @@ pub async fn get_queue(
         name,
         queue.entries().values().cloned().collect(),
         matchmaker,
+        queue.schema().cloned(),
+        queue.is_paused(),
     );
 
     let queue_data_json = serde_json::to_value(&queue_data);
@@ -151,3 +345,53 @@ pub async fn get_queue(
 
     (StatusCode::OK, Json(queue_data))
 }
+
+/// Explains why an entry in a queue is or isn't being matched. Nothing is removed from
+/// the queue.
+///
+/// **Request:**
+/// - Method: `GET`
+/// - Path: `/queues/{name}/explain/{entry_id}`
+/// - Path parameters:
+///   - `name` (String): Name of the queue.
+///   - `entry_id` (Uuid): Id of the entry.
+///
+/// **Response:**
+/// - `200 OK`: Returns the explanation.
+///   - Body: `{ "entry", "matchmaker", "summary", "details", "candidates": [{ "entry", "accepted", "reason" }] }`
+/// - `404 Not Found`: Queue or entry not found.
+///   - Body: `{ "error": "..." }`
+#[axum::debug_handler]
+pub async fn explain_entry(
+    app_state: State<AppState>,
+    Path((name, entry_id)): Path<(String, Uuid)>,
+) -> (StatusCode, Json<Value>) {
+    let registry = app_state.queue_tracker.lock().await;
+
+    let Some(queue) = registry.get_queue(&name).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": format!("Queue {} does not exist", name), "code": "QUEUE_NOT_FOUND"})),
+        );
+    };
+    drop(registry);
+
+    let queue = queue.lock().await;
+    let explanation = match queue.matchmaker().explain(&EntryId(entry_id)) {
+        Ok(explanation) => explanation,
+        Err(err) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({"error": err.to_string(), "code": err.code()})),
+            );
+        }
+    };
+
+    match serde_json::to_value(&explanation) {
+        Ok(json) => (StatusCode::OK, Json(json)),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": err.to_string()})),
+        ),
+    }
+}
@@ -50,7 +50,10 @@ pub async fn handle_socket(
             Err(err) => {
                 send_socket(
                     sender,
-                    Err(format!("Failed to parse join request: {}", err)),
+                    Err(QueueError::new(
+                        "INVALID_REQUEST",
+                        format!("Failed to parse join request: {}", err),
+                    )),
                 )
                 .await;
                 return;
@@ -58,7 +61,7 @@ pub async fn handle_socket(
         };
 
         let queue_name = &queue_name;
-        let id = queue_join_request.id.clone();
+        let id = queue_join_request.id;
 
         debug!("Parsed join request: {:?}", queue_join_request);
         let result = tokio::select! {
@@ -87,50 +90,49 @@ pub async fn join_queue(
     queue_name: &str,
     queue_join_request: QueueJoinRequest,
     queue_tracker: Arc<Mutex<QueueTracker>>,
-) -> Result<QueueResult, String> {
-    debug!("Waiting for queue tracker lock...");
-    let mut tracker_guard = queue_tracker.lock().await;
-
+) -> Result<QueueResult, QueueError> {
     let entry = Entry::new(
         queue_join_request.id,
         queue_join_request.players,
         queue_join_request.metadata,
     );
+    let players = QueueTracker::lookup(&queue_tracker, queue_name, &entry.players)
+        .await
+        .map_err(QueueError::from)?;
+
+    debug!("Waiting for queue tracker lock...");
+    let mut tracker_guard = queue_tracker.lock().await;
 
     let receiver = tracker_guard
-        .join(queue_name, entry)
+        .join(queue_name, entry, players)
         .await
-        .map_err(|x| x.to_string())?;
+        .map_err(QueueError::from)?;
 
     drop(tracker_guard);
 
-    QueueTracker::tick_task(queue_tracker, queue_name).await;
-
     debug!("Joined queue, waiting for queue result...");
 
-    let result = receiver.await.map_err(|x| x.to_string())??;
+    let result = receiver
+        .await
+        .map_err(|x| QueueError::new("INTERNAL_ERROR", x.to_string()))??;
     Ok(result)
 }
 
 async fn send_socket(
     mut sender: SplitSink<WebSocket, Message>,
-    socket_response: Result<QueueResult, String>,
+    socket_response: Result<QueueResult, QueueError>,
 ) {
-    let socket_response = socket_response.map_err(|x| QueueError::new(x));
-
     match serde_json::to_string(&socket_response) {
         Ok(json) => {
             match sender.send(Text(json.into())).await {
                 Ok(_) => {}
                 Err(err) => {
                     error!("Failed to send socket response: {}", err);
-                    return;
                 }
             };
         }
         Err(err) => {
             error!("Failed to serialize socket response: {}", err);
-            return;
         }
     };
 }
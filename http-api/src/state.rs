@@ -1,8 +1,12 @@
+use common::profile::ProfileStore;
 use common::queue_tracker::QueueTracker;
+use common::storage::Storage;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub queue_tracker: Arc<Mutex<QueueTracker>>
-}
\ No newline at end of file
+    pub queue_tracker: Arc<Mutex<QueueTracker>>,
+    pub storage: Arc<dyn Storage>,
+    pub profiles: Arc<dyn ProfileStore>,
+}
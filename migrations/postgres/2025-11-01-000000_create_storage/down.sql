@@ -0,0 +1,3 @@
+DROP TABLE matches;
+DROP TABLE entries;
+DROP TABLE queues;
@@ -0,0 +1,33 @@
+CREATE TABLE queues (
+    name         TEXT PRIMARY KEY,
+    matchmaker   TEXT    NOT NULL,
+    settings     JSONB   NOT NULL,
+    schema       JSONB,
+    tick         JSONB   NOT NULL,
+    paused       BOOLEAN NOT NULL DEFAULT FALSE,
+    reject_joins BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE entries (
+    id          UUID PRIMARY KEY,
+    queue       TEXT        NOT NULL REFERENCES queues (name) ON DELETE CASCADE,
+    players     UUID[]      NOT NULL,
+    metadata    JSONB       NOT NULL,
+    time_queued TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX entries_queue_idx ON entries (queue);
+
+CREATE TABLE matches (
+    id         UUID PRIMARY KEY,
+    queue      TEXT        NOT NULL,
+    teams      JSONB       NOT NULL,
+    players    UUID[]      NOT NULL,
+    game       JSONB       NOT NULL,
+    region     TEXT,
+    created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX matches_queue_created_at_idx ON matches (queue, created_at DESC);
+CREATE INDEX matches_created_at_idx ON matches (created_at DESC);
+CREATE INDEX matches_players_idx ON matches USING GIN (players);
@@ -0,0 +1,3 @@
+DROP TABLE ratings;
+
+ALTER TABLE matches DROP COLUMN placements;
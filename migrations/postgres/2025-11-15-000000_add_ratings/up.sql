@@ -0,0 +1,14 @@
+ALTER TABLE matches ADD COLUMN placements INTEGER[];
+
+CREATE TABLE ratings (
+    queue             TEXT             NOT NULL,
+    player            UUID             NOT NULL,
+    elo               DOUBLE PRECISION NOT NULL,
+    glicko_rating     DOUBLE PRECISION NOT NULL,
+    glicko_rd         DOUBLE PRECISION NOT NULL,
+    glicko_volatility DOUBLE PRECISION NOT NULL,
+    matches           INTEGER          NOT NULL,
+    wins              INTEGER          NOT NULL,
+    updated_at        TIMESTAMPTZ      NOT NULL,
+    PRIMARY KEY (queue, player)
+);
@@ -0,0 +1,4 @@
+CREATE TABLE profiles (
+    player  UUID  PRIMARY KEY,
+    profile JSONB NOT NULL
+);
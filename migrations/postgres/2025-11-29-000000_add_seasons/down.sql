@@ -0,0 +1,9 @@
+DROP TABLE seasons;
+
+DROP INDEX ratings_glicko_idx;
+DROP INDEX ratings_elo_idx;
+
+DELETE FROM ratings WHERE season <> 1;
+ALTER TABLE ratings DROP CONSTRAINT ratings_pkey;
+ALTER TABLE ratings DROP COLUMN season;
+ALTER TABLE ratings ADD PRIMARY KEY (queue, player);
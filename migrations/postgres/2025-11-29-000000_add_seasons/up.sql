@@ -0,0 +1,12 @@
+ALTER TABLE ratings ADD COLUMN season INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE ratings DROP CONSTRAINT ratings_pkey;
+ALTER TABLE ratings ADD PRIMARY KEY (queue, season, player);
+
+CREATE INDEX ratings_elo_idx ON ratings (queue, season, elo DESC, player);
+CREATE INDEX ratings_glicko_idx ON ratings (queue, season, glicko_rating DESC, player);
+
+CREATE TABLE seasons (
+    queue      TEXT        PRIMARY KEY,
+    season     INTEGER     NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,4 @@
+DROP TABLE match_players;
+DROP TABLE matches;
+DROP TABLE entries;
+DROP TABLE queues;
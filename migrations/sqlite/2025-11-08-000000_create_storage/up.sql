@@ -0,0 +1,39 @@
+CREATE TABLE queues (
+    name         TEXT    PRIMARY KEY NOT NULL,
+    matchmaker   TEXT    NOT NULL,
+    settings     TEXT    NOT NULL,
+    schema       TEXT,
+    tick         TEXT    NOT NULL,
+    paused       BOOLEAN NOT NULL DEFAULT FALSE,
+    reject_joins BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE entries (
+    id          TEXT PRIMARY KEY NOT NULL,
+    queue       TEXT NOT NULL REFERENCES queues (name) ON DELETE CASCADE,
+    players     TEXT NOT NULL,
+    metadata    TEXT NOT NULL,
+    time_queued TIMESTAMP NOT NULL
+);
+
+CREATE INDEX entries_queue_idx ON entries (queue);
+
+CREATE TABLE matches (
+    id         TEXT PRIMARY KEY NOT NULL,
+    queue      TEXT NOT NULL,
+    teams      TEXT NOT NULL,
+    game       TEXT NOT NULL,
+    region     TEXT,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX matches_queue_created_at_idx ON matches (queue, created_at);
+CREATE INDEX matches_created_at_idx ON matches (created_at);
+
+CREATE TABLE match_players (
+    match_id TEXT NOT NULL REFERENCES matches (id) ON DELETE CASCADE,
+    player   TEXT NOT NULL,
+    PRIMARY KEY (match_id, player)
+);
+
+CREATE INDEX match_players_player_idx ON match_players (player);
@@ -0,0 +1,14 @@
+ALTER TABLE matches ADD COLUMN placements TEXT;
+
+CREATE TABLE ratings (
+    queue             TEXT      NOT NULL,
+    player            TEXT      NOT NULL,
+    elo               DOUBLE    NOT NULL,
+    glicko_rating     DOUBLE    NOT NULL,
+    glicko_rd         DOUBLE    NOT NULL,
+    glicko_volatility DOUBLE    NOT NULL,
+    matches           INTEGER   NOT NULL,
+    wins              INTEGER   NOT NULL,
+    updated_at        TIMESTAMP NOT NULL,
+    PRIMARY KEY (queue, player)
+);
@@ -0,0 +1,4 @@
+CREATE TABLE profiles (
+    player  TEXT PRIMARY KEY NOT NULL,
+    profile TEXT NOT NULL
+);
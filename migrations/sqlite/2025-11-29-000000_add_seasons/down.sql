@@ -0,0 +1,20 @@
+DROP TABLE seasons;
+
+CREATE TABLE ratings_by_player (
+    queue             TEXT      NOT NULL,
+    player            TEXT      NOT NULL,
+    elo               DOUBLE    NOT NULL,
+    glicko_rating     DOUBLE    NOT NULL,
+    glicko_rd         DOUBLE    NOT NULL,
+    glicko_volatility DOUBLE    NOT NULL,
+    matches           INTEGER   NOT NULL,
+    wins              INTEGER   NOT NULL,
+    updated_at        TIMESTAMP NOT NULL,
+    PRIMARY KEY (queue, player)
+);
+
+INSERT INTO ratings_by_player
+SELECT queue, player, elo, glicko_rating, glicko_rd, glicko_volatility, matches, wins, updated_at
+FROM ratings WHERE season = 1;
+DROP TABLE ratings;
+ALTER TABLE ratings_by_player RENAME TO ratings;
@@ -0,0 +1,28 @@
+-- SQLite can't change a primary key, so the table is copied into a new one
+CREATE TABLE ratings_by_season (
+    queue             TEXT      NOT NULL,
+    season            INTEGER   NOT NULL,
+    player            TEXT      NOT NULL,
+    elo               DOUBLE    NOT NULL,
+    glicko_rating     DOUBLE    NOT NULL,
+    glicko_rd         DOUBLE    NOT NULL,
+    glicko_volatility DOUBLE    NOT NULL,
+    matches           INTEGER   NOT NULL,
+    wins              INTEGER   NOT NULL,
+    updated_at        TIMESTAMP NOT NULL,
+    PRIMARY KEY (queue, season, player)
+);
+
+INSERT INTO ratings_by_season
+SELECT queue, 1, player, elo, glicko_rating, glicko_rd, glicko_volatility, matches, wins, updated_at FROM ratings;
+DROP TABLE ratings;
+ALTER TABLE ratings_by_season RENAME TO ratings;
+
+CREATE INDEX ratings_elo_idx ON ratings (queue, season, elo DESC, player);
+CREATE INDEX ratings_glicko_idx ON ratings (queue, season, glicko_rating DESC, player);
+
+CREATE TABLE seasons (
+    queue      TEXT      PRIMARY KEY NOT NULL,
+    season     INTEGER   NOT NULL,
+    started_at TIMESTAMP NOT NULL
+);